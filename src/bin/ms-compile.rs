@@ -0,0 +1,28 @@
+//! Compiles the plain-text source form of a MessageStore into the binary form
+//!
+//! Usage: ms-compile MESSAGES.ms OUT.bin
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use anyhow::{bail, Context, Result};
+
+use index_datamanip::parse_messages;
+
+fn main() -> Result<()> {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    if args.len() != 2 {
+        bail!("Usage: ms-compile MESSAGES.ms OUT.bin");
+    }
+
+    let input =
+        fs::read_to_string(&args[0]).with_context(|| format!("failed to read {}", args[0]))?;
+    let mut out = BufWriter::new(
+        File::create(&args[1]).with_context(|| format!("failed to create {}", args[1]))?,
+    );
+    parse_messages::compile_text(&input, &mut out)
+        .with_context(|| format!("failed to compile {}", args[0]))?;
+    out.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+//! Lists the differences between two piggs, such as before and after a patch
+//!
+//! Usage: pigg-diff [--bytes] OLD.pigg NEW.pigg
+//!
+//! Decoded bin records can only be compared through the library's
+//! `pigg_diff::diff_bins`, as it needs to know each bin's record type.
+
+use anyhow::{bail, Context, Result};
+
+use index_datamanip::pigg_diff::{self, Change};
+use index_datamanip::Pigg;
+
+fn main() -> Result<()> {
+    let mut bytes = false;
+    let mut paths = vec![];
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--bytes" => bytes = true,
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 2 {
+        bail!("Usage: pigg-diff [--bytes] OLD.pigg NEW.pigg");
+    }
+
+    let old = Pigg::new(&paths[0]).with_context(|| format!("failed to open {}", paths[0]))?;
+    let new = Pigg::new(&paths[1]).with_context(|| format!("failed to open {}", paths[1]))?;
+
+    for change in pigg_diff::diff(&old, &new) {
+        match &change {
+            Change::Added { path, new } => println!("A {} ({} bytes)", path, new.size),
+            Change::Removed { path, old } => println!("D {} ({} bytes)", path, old.size),
+            Change::Touched { path, old, new } => {
+                println!("T {} ({} -> {})", path, old.timestamp, new.timestamp)
+            }
+            Change::Changed {
+                path,
+                old: old_meta,
+                new: new_meta,
+            } => {
+                println!("M {} ({} -> {} bytes)", path, old_meta.size, new_meta.size);
+                if bytes {
+                    let old_data = old.get_data_cow(path)?;
+                    let new_data = new.get_data_cow(path)?;
+                    for range in pigg_diff::diff_bytes(&old_data, &new_data) {
+                        println!("    {:#x}..{:#x}", range.start, range.end);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,9 +1,14 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Write;
 use std::str;
 
+use encoding_rs::WINDOWS_1252;
 use nom::bytes::complete::*;
 use nom::number::complete::*;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 
 /// Determine how many extra bytes are necessary to round the given length up to a 32byte alignment
 pub(crate) fn needed_padding(length: usize) -> usize {
@@ -23,3 +28,106 @@ pub(crate) fn parse_lstring(input: &[u8]) -> Result<(&[u8], String)> {
 
     Ok((input, str::from_utf8(str_bytes)?.to_string()))
 }
+
+/// Writes a length-prefixed string, as read by `parse_lstring`
+pub(crate) fn write_lstring(out: &mut Vec<u8>, bytes: &[u8]) -> Result<()> {
+    let len = u16::try_from(bytes.len())
+        .map_err(|_| Error::EncodeError(format!("{} byte string is too long", bytes.len())))?;
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(bytes);
+    out.resize(
+        out.len() + needed_padding(len as usize + std::mem::size_of::<u16>()),
+        0,
+    );
+    Ok(())
+}
+
+/// Converts a length or count for writing into a 32bit field, naming `what`
+/// is too large if it doesn't fit
+pub(crate) fn to_u32(value: usize, what: &str) -> Result<u32> {
+    u32::try_from(value).map_err(|_| Error::EncodeError(format!("{} is too large", what)))
+}
+
+/// Writes a little endian u16
+pub(crate) fn write_u16<W: Write>(out: &mut W, value: u16) -> Result<()> {
+    out.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+/// Writes a little endian u32
+pub(crate) fn write_u32<W: Write>(out: &mut W, value: u32) -> Result<()> {
+    out.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+/// A table of NUL-terminated strings being built up for writing, holding each
+/// string once
+///
+/// MessageStores refer to strings by index, and bins by byte offset, so both
+/// are tracked.
+#[derive(Debug, Default)]
+pub(crate) struct StringTable {
+    bytes: Vec<u8>,
+    entries: HashMap<String, StringEntry>,
+}
+
+/// Where a string was placed in a `StringTable`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct StringEntry {
+    /// How many strings precede it
+    pub index: u32,
+    /// How many bytes precede it
+    pub offset: u32,
+}
+
+impl StringTable {
+    /// Adds a string if it isn't already present, returning where it is
+    pub fn add(&mut self, s: &str) -> Result<StringEntry> {
+        if let Some(&entry) = self.entries.get(s) {
+            return Ok(entry);
+        }
+        if s.contains('\0') {
+            return Err(Error::EncodeError(format!("{:?} contains a NUL", s)));
+        }
+        let entry = StringEntry {
+            index: to_u32(self.entries.len(), "string table")?,
+            offset: to_u32(self.bytes.len(), "string table")?,
+        };
+        self.bytes.extend_from_slice(s.as_bytes());
+        self.bytes.push(0);
+        self.entries.insert(s.to_string(), entry);
+        Ok(entry)
+    }
+
+    /// Number of strings in the table
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The strings in the order they were added, each followed by a NUL
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Decodes a string from CoH's legacy code page, Windows-1252
+///
+/// Every byte maps to its own character, so this can't fail and encoding the
+/// result gives back the same bytes.
+pub(crate) fn decode_cp1252(bytes: &[u8]) -> String {
+    WINDOWS_1252
+        .decode_without_bom_handling(bytes)
+        .0
+        .into_owned()
+}
+
+/// Encodes a string into Windows-1252, or `None` if it holds characters the
+/// code page can't represent
+pub(crate) fn encode_cp1252(s: &str) -> Option<Cow<'_, [u8]>> {
+    let (bytes, _, had_errors) = WINDOWS_1252.encode(s);
+    if had_errors {
+        None
+    } else {
+        Some(bytes)
+    }
+}
@@ -2,64 +2,238 @@
 
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::marker::PhantomData;
 
 use nom::bytes::complete::*;
 use nom::number::complete::*;
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 
 use crate::common::*;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::parse7;
 
+/// How the adler32 checksum in a bin's header is treated
+///
+/// The checksum is taken to cover everything after itself, which matches
+/// bins written by `encode` but hasn't been confirmed against bins written by
+/// the game, so it isn't checked by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /// A mismatch is an error
+    Strict,
+    /// A mismatch is returned as a warning, and decoding continues
+    Warn,
+    /// The checksum isn't checked
+    #[default]
+    Skip,
+}
+
 /// Deserialize the bin data into a vec of T
 ///
-/// Only Parse7 (HC, SCoRE) encoded bins are supported
+/// Only Parse7 (HC, SCoRE) encoded bins are supported. Records which fail to
+/// parse are reported and skipped, while a malformed file is an error. See
+/// `decode_iter` to process records as they're parsed.
 ///
-/// TODO: This really should be an iterator, not a vec. It would offer better
-/// performance if data is immediately transformed, and be trivially collected
-/// into a vec if that's all that's desired
+/// The checksum isn't checked, so a corrupted bin is only caught if it fails
+/// to parse. See `decode_with` to check it, and `ChecksumMode` for why it
+/// isn't checked by default.
 pub fn decode<T: DeserializeOwned + Debug>(input: &[u8]) -> Result<Vec<T>> {
-    let (input, text_size) = parse_header(input)?;
-    let (input, strings) = parse_strings_list(input, text_size)?;
-    let (input, items) = parse_binary(input, &strings)?;
-
-    // TODO: Change to erroring out
-    assert!(input.is_empty(), "Data unexpectedly found at end of file");
-
+    let (items, warnings) = decode_with(input, ChecksumMode::default())?;
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
     Ok(items)
 }
 
-fn is_nonnull(c: u8) -> bool {
-    c != 0
+/// Deserialize the bin data into a vec of T, checking its checksum as
+/// `mode` says
+///
+/// Rather than being printed, a `ParseError` for each record which failed to
+/// parse is returned alongside the records, after the checksum mismatch in
+/// `ChecksumMode::Warn`. See `decode`
+pub fn decode_with<T: DeserializeOwned + Debug>(
+    input: &[u8],
+    mode: ChecksumMode,
+) -> Result<(Vec<T>, Vec<Error>)> {
+    let mut records = decode_iter_with::<T>(input, mode)?;
+    let mut items = vec![];
+    let mut warnings = records
+        .checksum_mismatch
+        .take()
+        .into_iter()
+        .collect::<Vec<_>>();
+    while let Some(record) = records.next_record() {
+        match record? {
+            Ok(item) => items.push(item),
+            Err(err) => warnings.push(Error::ParseError(format!(
+                "failed to parse binary index {}: {}",
+                records.index - 1,
+                err
+            ))),
+        }
+    }
+    Ok((items, warnings))
 }
 
-fn parse_binary<'a, T: DeserializeOwned + Debug>(
-    input: &'a [u8],
-    strings: &HashMap<u32, String>,
-) -> Result<(&'a [u8], Vec<T>)> {
+/// Deserialize the bin data one record at a time
+///
+/// The header and string table are parsed up front, and each record is only
+/// parsed as the iterator reaches it. A record which fails to parse yields an
+/// error without stopping the iteration, while a malformed file yields a
+/// final error.
+///
+/// The checksum isn't checked, see `decode_iter_with` to check it.
+pub fn decode_iter<T: DeserializeOwned>(input: &[u8]) -> Result<DecodeIter<'_, T>> {
+    decode_iter_with(input, ChecksumMode::default())
+}
+
+/// Deserialize the bin data one record at a time, checking its checksum as
+/// `mode` says
+///
+/// The checksum covers the whole file, so it's checked up front. In
+/// `ChecksumMode::Warn`, a mismatch is available from
+/// `DecodeIter::checksum_mismatch`. See `decode_iter`
+pub fn decode_iter_with<T: DeserializeOwned>(
+    input: &[u8],
+    mode: ChecksumMode,
+) -> Result<DecodeIter<'_, T>> {
+    let (input, text_size, checksum_mismatch) = parse_header(input, mode)?;
+    let (input, strings) = parse_strings_list(input, text_size)?;
     let (input, _binary_size) = le_u32(input)?;
     let (input, count) = le_u32(input)?;
 
-    let mut input = input;
-    let mut items = vec![];
-    for i in 0..count {
-        let (_, chunk_size) = le_u32(input)?;
-        let (new_input, chunk) = take(chunk_size + 4)(input)?;
-        input = new_input;
+    Ok(DecodeIter {
+        input,
+        strings,
+        index: 0,
+        count,
+        done: false,
+        checksum_mismatch,
+        marker: PhantomData,
+    })
+}
 
-        match parse7::parse::<T>(chunk, &strings) {
-            Ok(item) => items.push(item),
-            Err(err) => eprintln!("Failed to parse binary index {}: {}", i, err),
+/// Iterator over the records of a bin, see `decode_iter`
+pub struct DecodeIter<'a, T> {
+    input: &'a [u8],
+    strings: HashMap<u32, String>,
+    index: u32,
+    count: u32,
+    done: bool,
+    checksum_mismatch: Option<Error>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> DecodeIter<'_, T> {
+    /// Number of records in the bin, including those already yielded
+    pub fn record_count(&self) -> u32 {
+        self.count
+    }
+
+    /// The `ChecksumMismatch` found in `ChecksumMode::Warn`, if any
+    pub fn checksum_mismatch(&self) -> Option<&Error> {
+        self.checksum_mismatch.as_ref()
+    }
+
+    /// Parses the next record, keeping problems with the file (the outer
+    /// result) apart from problems with the record itself (the inner one)
+    pub(crate) fn next_record(&mut self) -> Option<Result<Result<T>>> {
+        if self.done {
+            return None;
+        }
+        if self.index == self.count {
+            self.done = true;
+            if self.input.is_empty() {
+                return None;
+            }
+            return Some(Err(Error::ParseError(format!(
+                "{} bytes left over after parsing the binary section",
+                self.input.len()
+            ))));
+        }
+
+        let chunk = le_u32(self.input)
+            .and_then(|(_, chunk_size)| take(chunk_size.saturating_add(4))(self.input))
+            .map_err(Error::from);
+        let (new_input, chunk) = match chunk {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err));
+            }
         };
+        self.input = new_input;
+        self.index += 1;
+
+        Some(Ok(parse7::parse::<T>(chunk, &self.strings)))
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for DecodeIter<'_, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        self.next_record()
+            .map(|record| record.and_then(|item| item))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.count - self.index) as usize;
+        // A malformed file may yield one extra error
+        (0, Some(remaining + 1))
+    }
+}
+
+/// Serialize a slice of T into a Parse7 bin, which `decode` reads back
+///
+/// Strings are gathered into the bin's string table in the order they're
+/// first written, with each distinct string stored once. The binary size,
+/// which `decode` doesn't read, is written as the size of the record count
+/// and records. Neither has been checked against bins written by the game,
+/// so re-encoding one isn't guaranteed to give back the same bytes.
+///
+/// Types which `decode` knows it only reads part of, such as `Class`, fail
+/// to encode rather than silently dropping the rest of their data.
+/// Likewise, a `Some` whose value is written starting with a zero, such as
+/// `Some(0)`, `Some("")`, or `Some(vec![])`, fails as it would read back as
+/// `None`.
+pub fn encode<T: Serialize>(items: &[T]) -> Result<Vec<u8>> {
+    // Offset zero is the empty string
+    let mut strings = StringTable::default();
+    strings.add("")?;
+    let mut records = vec![];
+    for item in items {
+        records.extend(parse7::serialize(item, &mut strings)?);
     }
+    let count = to_u32(items.len(), "record count")?;
 
-    // TODO: Make an error (or just a warning?)
-    assert!(
-        input.is_empty(),
-        "Shouldn't be any data left over after parsing the binary section"
+    // Everything after the checksum, which is what it covers
+    let mut body = vec![];
+    write_lstring(&mut body, b"Parse7")?;
+    let text = strings.as_bytes();
+    body.extend_from_slice(&to_u32(text.len(), "string table")?.to_le_bytes());
+    body.extend_from_slice(text);
+    body.resize(body.len() + needed_padding(text.len()), 0);
+    // Covers the record count as well as the records
+    let binary_size = to_u32(records.len() + 4, "binary section")?;
+    body.extend_from_slice(&binary_size.to_le_bytes());
+    body.extend_from_slice(&count.to_le_bytes());
+    body.extend_from_slice(&records);
+
+    let mut out = Vec::with_capacity(12 + body.len());
+    out.extend_from_slice(b"CrypticS");
+    out.extend_from_slice(
+        &adler32::RollingAdler32::from_buffer(&body)
+            .hash()
+            .to_le_bytes(),
     );
+    out.extend_from_slice(&body);
+    Ok(out)
+}
 
-    Ok((input, items))
+fn is_nonnull(c: u8) -> bool {
+    c != 0
 }
 
 fn parse_strings_list(input: &[u8], text_size: u32) -> Result<(&[u8], HashMap<u32, String>)> {
@@ -81,20 +255,226 @@ fn parse_strings_list(input: &[u8], text_size: u32) -> Result<(&[u8], HashMap<u3
     Ok((ret_input, strings))
 }
 
-fn parse_header(input: &[u8]) -> Result<(&[u8], u32)> {
+/// Parses the header, returning the size of the string table and any
+/// checksum mismatch to warn about
+fn parse_header(input: &[u8], mode: ChecksumMode) -> Result<(&[u8], u32, Option<Error>)> {
     let (input, _) = tag(b"CrypticS")(input)?;
 
-    // Extract checksum and ignore it
-    let (input, _addler32) = le_u32(input)?;
+    // Covers everything after itself
+    let (input, expected) = le_u32(input)?;
+    let mut mismatch = None;
+    if mode != ChecksumMode::Skip {
+        let actual = adler32::RollingAdler32::from_buffer(input).hash();
+        if actual != expected {
+            let err = Error::ChecksumMismatch { expected, actual };
+            match mode {
+                ChecksumMode::Strict => return Err(err),
+                _ => mismatch = Some(err),
+            }
+        }
+    }
 
     let (input, sig) = parse_lstring(input)?;
-    // TODO: Make an error
-    assert!(sig == "Parse7", "Failed to find Parse7 sig: Found: {}", sig);
+    if sig != "Parse7" {
+        return Err(Error::ParseError(format!(
+            "expected a Parse7 bin, found {:?}",
+            sig
+        )));
+    }
 
     // let (input, sig) = parse_lstring(input)?;
     // assert!(sig == "Files1", "Failed to find Files1 sig");
 
     let (input, names_size) = le_u32(input)?;
 
-    Ok((input, names_size))
+    Ok((input, names_size, mismatch))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    enum Kind {
+        Melee,
+        Ranged,
+    }
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct Inner {
+        values: Vec<f32>,
+        label: String,
+    }
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct Record {
+        name: String,
+        kind: Kind,
+        level: Option<u32>,
+        flag: bool,
+        tags: Vec<String>,
+        inner: Vec<Inner>,
+    }
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                name: "Fire_Blast".into(),
+                kind: Kind::Ranged,
+                level: Some(1),
+                flag: true,
+                tags: vec!["Fire".into(), "".into()],
+                inner: vec![Inner {
+                    values: vec![0.5, 2.0],
+                    label: "Fire".into(),
+                }],
+            },
+            Record {
+                name: "Punch".into(),
+                kind: Kind::Melee,
+                level: None,
+                flag: false,
+                tags: vec![],
+                inner: vec![],
+            },
+        ]
+    }
+
+    #[test]
+    fn encoded_bin_decodes_back() {
+        let bytes = encode(&records()).unwrap();
+        let (decoded, warnings): (Vec<Record>, _) =
+            decode_with(&bytes, ChecksumMode::Strict).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(decoded, records());
+        assert_eq!(encode(&decoded).unwrap(), bytes);
+    }
+
+    #[test]
+    fn options_round_trip_unless_ambiguous() {
+        #[derive(Debug, Deserialize, Serialize, PartialEq)]
+        struct R {
+            a: Option<u32>,
+            s: Option<String>,
+            v: Option<Vec<u32>>,
+        }
+
+        let some = R {
+            a: Some(7),
+            s: Some("name".into()),
+            v: Some(vec![0]),
+        };
+        let none = R {
+            a: None,
+            s: None,
+            v: None,
+        };
+        let bytes = encode(&[some, none]).unwrap();
+        let decoded: Vec<R> = decode(&bytes).unwrap();
+        assert_eq!(decoded[0].a, Some(7));
+        assert_eq!(decoded[0].s.as_deref(), Some("name"));
+        assert_eq!(decoded[0].v, Some(vec![0]));
+        assert_eq!(
+            decoded[1],
+            R {
+                a: None,
+                s: None,
+                v: None
+            }
+        );
+
+        // Each of these would be written as a zero, which reads back as None
+        let zero = R {
+            a: Some(0),
+            s: None,
+            v: None,
+        };
+        let empty_string = R {
+            a: None,
+            s: Some(String::new()),
+            v: None,
+        };
+        let empty_vec = R {
+            a: None,
+            s: None,
+            v: Some(vec![]),
+        };
+        for ambiguous in [zero, empty_string, empty_vec] {
+            assert!(matches!(encode(&[ambiguous]), Err(Error::EncodeError(_))));
+        }
+    }
+
+    #[test]
+    fn incomplete_structs_fail_to_encode() {
+        #[derive(Serialize)]
+        #[serde(rename = "Class")]
+        struct Class {
+            name: String,
+        }
+
+        let class = Class {
+            name: "Class_Blaster".into(),
+        };
+        assert!(matches!(encode(&[class]), Err(Error::EncodeError(_))));
+    }
+
+    #[test]
+    fn checksum_is_only_checked_when_asked() {
+        let mut bytes = encode(&records()).unwrap();
+        // The stored checksum follows the magic
+        bytes[8] ^= 0xff;
+
+        assert_eq!(decode::<Record>(&bytes).unwrap().len(), 2);
+        let (decoded, warnings) = decode_with::<Record>(&bytes, ChecksumMode::Warn).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert!(matches!(warnings[..], [Error::ChecksumMismatch { .. }]));
+        assert!(decode_iter_with::<Record>(&bytes, ChecksumMode::Warn)
+            .unwrap()
+            .checksum_mismatch()
+            .is_some());
+        assert!(matches!(
+            decode_with::<Record>(&bytes, ChecksumMode::Strict),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn other_signatures_are_an_error() {
+        let mut bytes = encode(&records()).unwrap();
+        // The signature follows the magic, checksum, and its length
+        bytes[14..20].copy_from_slice(b"Files1");
+        assert!(matches!(
+            decode::<Record>(&bytes),
+            Err(Error::ParseError(_))
+        ));
+    }
+
+    /// Reads a bin written by the game, which isn't included here
+    ///
+    /// Tests using it are run with `INDEX_DATAMANIP_POWERCATS_BIN` set to the
+    /// path of an extracted `bin/powercats.bin`, and `--ignored`.
+    fn game_bin() -> Vec<u8> {
+        let path = std::env::var("INDEX_DATAMANIP_POWERCATS_BIN")
+            .expect("INDEX_DATAMANIP_POWERCATS_BIN isn't set");
+        std::fs::read(path).unwrap()
+    }
+
+    #[test]
+    #[ignore]
+    fn game_bin_checksum_covers_everything_after_it() {
+        decode_iter_with::<crate::objects::PowerCategory>(&game_bin(), ChecksumMode::Strict)
+            .unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    fn game_bin_reencodes_to_the_same_bytes() {
+        let bytes = game_bin();
+        let (decoded, warnings): (Vec<crate::objects::PowerCategory>, _) =
+            decode_with(&bytes, ChecksumMode::Skip).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(encode(&decoded).unwrap(), bytes);
+    }
 }
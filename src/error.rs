@@ -14,6 +14,42 @@ pub enum Error {
     /// attempted to reference other data which didn't exist.
     #[error("failed to parse: {0}")]
     ParseError(String),
+    /// The data was unable to be written out in the expected format, such as
+    /// when it's too large for the format's size fields
+    #[error("failed to encode: {0}")]
+    EncodeError(String),
+    /// The data was written in a newer version of its format than this
+    /// library can read
+    #[error("unsupported {format} version: {version}")]
+    UnsupportedVersion {
+        /// Which format the version belongs to, such as `pigg`
+        format: &'static str,
+        /// The version the data requires
+        version: u32,
+    },
+    /// A MessageStore was malformed
+    #[error("failed to parse MessageStore version {version} at byte {offset}: {reason}")]
+    MessageStoreError {
+        /// The version the MessageStore claims to be, or 0 if it's too short
+        /// to hold one
+        version: u32,
+        /// Where in the MessageStore the problem was found
+        offset: usize,
+        /// What was wrong
+        reason: String,
+    },
+    /// The data didn't match the checksum stored alongside it
+    #[error("checksum mismatch: expected {expected:#010x}, found {actual:#010x}")]
+    ChecksumMismatch {
+        /// The checksum stored in the data
+        expected: u32,
+        /// The checksum of the data as read
+        actual: u32,
+    },
+    /// A `PiggUpdater` commit to the pigg was interrupted, leaving its
+    /// headers possibly torn until `PiggUpdater::recover` finishes it
+    #[error("interrupted update to {}, run PiggUpdater::recover", .0.display())]
+    InterruptedUpdate(std::path::PathBuf),
     /// The requested item wasn't found
     #[error("item not found in collection: {0}")]
     ItemNotFound(String),
@@ -28,6 +64,12 @@ impl serde::de::Error for Error {
     }
 }
 
+impl serde::ser::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error::EncodeError(msg.to_string())
+    }
+}
+
 impl std::convert::From<nom::Err<(&[u8], nom::error::ErrorKind)>> for Error {
     fn from(err: nom::Err<(&[u8], nom::error::ErrorKind)>) -> Self {
         Error::ParseError(format!("{}", err))
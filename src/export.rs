@@ -0,0 +1,158 @@
+use std::borrow::Cow;
+use std::io::{Seek, Write};
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+use crate::error::{Error, Result};
+use crate::pigg::FileMetadata;
+
+/// Name of the manifest added alongside the exported files
+///
+/// It lists each file's checksum from the pigg in `md5sum` format, so the
+/// extracted files can be checked with `md5sum -c`.
+pub const MANIFEST_NAME: &str = "pigg-manifest.md5";
+
+/// Streams the files into a tar archive, sorted by path, followed by the
+/// manifest
+pub(crate) fn to_tar<'a, G, W>(
+    mut files: Vec<(&'a str, FileMetadata)>,
+    get_data: G,
+    out: W,
+) -> Result<W>
+where
+    G: Fn(&'a str) -> Result<Cow<'a, [u8]>>,
+    W: Write,
+{
+    files.sort_by_key(|(path, _)| *path);
+
+    let mut builder = tar::Builder::new(out);
+    for (path, metadata) in &files {
+        let data = get_data(path)?;
+        let mut header = tar_header(data.len(), metadata.timestamp);
+        builder.append_data(&mut header, path, &data[..])?;
+    }
+
+    let manifest = manifest(&files);
+    let newest = files.iter().map(|(_, m)| m.timestamp).max().unwrap_or(0);
+    let mut header = tar_header(manifest.len(), newest);
+    builder.append_data(&mut header, MANIFEST_NAME, manifest.as_bytes())?;
+
+    Ok(builder.into_inner()?)
+}
+
+/// Streams the files into a zip archive, sorted by path, followed by the
+/// manifest
+pub(crate) fn to_zip<'a, G, W>(
+    mut files: Vec<(&'a str, FileMetadata)>,
+    get_data: G,
+    out: W,
+) -> Result<W>
+where
+    G: Fn(&'a str) -> Result<Cow<'a, [u8]>>,
+    W: Write + Seek,
+{
+    files.sort_by_key(|(path, _)| *path);
+
+    let mut zip = ZipWriter::new(out);
+    for (path, metadata) in &files {
+        let data = get_data(path)?;
+        zip.start_file(*path, zip_options(metadata.timestamp))
+            .map_err(zip_error)?;
+        zip.write_all(&data)?;
+    }
+
+    let newest = files.iter().map(|(_, m)| m.timestamp).max().unwrap_or(0);
+    zip.start_file(MANIFEST_NAME, zip_options(newest))
+        .map_err(zip_error)?;
+    zip.write_all(manifest(&files).as_bytes())?;
+
+    zip.finish().map_err(zip_error)
+}
+
+fn manifest(files: &[(&str, FileMetadata)]) -> String {
+    files
+        .iter()
+        .map(|(path, metadata)| {
+            let digest: String = metadata
+                .checksum
+                .to_le_bytes()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            format!("{}  {}\n", digest, path)
+        })
+        .collect()
+}
+
+fn tar_header(size: usize, timestamp: u32) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size as u64);
+    header.set_mtime(timestamp.into());
+    header.set_mode(0o644);
+    header.set_entry_type(tar::EntryType::Regular);
+    header
+}
+
+fn zip_options(timestamp: u32) -> FileOptions {
+    let (year, month, day, hour, minute, second) = civil_from_unix(timestamp);
+    // Zip can't represent anything before 1980, so fall back to its epoch
+    let modified =
+        DateTime::from_date_and_time(year, month, day, hour, minute, second).unwrap_or_default();
+    FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(modified)
+        .unix_permissions(0o644)
+}
+
+fn zip_error(err: zip::result::ZipError) -> Error {
+    match err {
+        zip::result::ZipError::Io(err) => Error::IoError(err),
+        err => Error::EncodeError(err.to_string()),
+    }
+}
+
+/// Converts seconds since the unix epoch to a UTC (year, month, day, hour,
+/// minute, second)
+///
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_unix(timestamp: u32) -> (u16, u8, u8, u8, u8, u8) {
+    let days = i64::from(timestamp / 86_400);
+    let secs = timestamp % 86_400;
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (
+        year as u16,
+        month as u8,
+        day as u8,
+        (secs / 3_600) as u8,
+        (secs / 60 % 60) as u8,
+        (secs % 60) as u8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_from_unix_matches_known_dates() {
+        assert_eq!(civil_from_unix(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(civil_from_unix(315_532_800), (1980, 1, 1, 0, 0, 0));
+        // Leap days, including 2000 which is divisible by 400
+        assert_eq!(civil_from_unix(951_782_400), (2000, 2, 29, 0, 0, 0));
+        assert_eq!(civil_from_unix(1_709_210_096), (2024, 2, 29, 12, 34, 56));
+        // Past where a signed 32bit time_t runs out, up to the largest u32
+        assert_eq!(civil_from_unix(2_147_483_648), (2038, 1, 19, 3, 14, 8));
+        assert_eq!(civil_from_unix(u32::MAX), (2106, 2, 7, 6, 28, 15));
+    }
+}
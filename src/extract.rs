@@ -0,0 +1,68 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use filetime::FileTime;
+use rayon::prelude::*;
+
+use crate::error::{Error, Result};
+use crate::loose_files::resolve_path;
+use crate::pigg::FileMetadata;
+
+/// Reported after each file is written by `extract_all`
+#[derive(Clone, Copy, Debug)]
+pub struct ExtractProgress<'a> {
+    /// Path of the file just written
+    pub path: &'a str,
+    /// Number of files written so far, including this one
+    pub extracted: usize,
+    /// Number of files being extracted in total
+    pub total: usize,
+}
+
+/// Decompresses the files across rayon's thread pool, writing each under
+/// `dest` with its modification time set from the pigg
+///
+/// Stops at the first error, though files already being written on other
+/// threads may still finish.
+pub(crate) fn extract_all<'a, G, C>(
+    files: Vec<(&'a str, FileMetadata)>,
+    get_data: G,
+    dest: &Path,
+    progress: C,
+) -> Result<usize>
+where
+    G: Fn(&'a str) -> Result<Cow<'a, [u8]>> + Sync,
+    C: Fn(ExtractProgress<'_>) + Sync,
+{
+    let total = files.len();
+    let extracted = AtomicUsize::new(0);
+
+    files
+        .into_par_iter()
+        .try_for_each(|(path, metadata)| -> Result<()> {
+            let out_path = resolve_path(dest, path).ok_or_else(|| {
+                Error::ParseError(format!(
+                    "{} would be extracted outside of the destination",
+                    path
+                ))
+            })?;
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            fs::write(&out_path, get_data(path)?)?;
+            let mtime = FileTime::from_unix_time(metadata.timestamp.into(), 0);
+            filetime::set_file_mtime(&out_path, mtime)?;
+
+            progress(ExtractProgress {
+                path,
+                extracted: extracted.fetch_add(1, Ordering::Relaxed) + 1,
+                total,
+            });
+            Ok(())
+        })?;
+
+    Ok(total)
+}
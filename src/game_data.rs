@@ -0,0 +1,172 @@
+use std::borrow::Cow;
+use std::convert::AsRef;
+use std::path::Path;
+
+use memmap::Mmap;
+
+use crate::error::{Error, Result};
+use crate::loose_files::LooseFiles;
+use crate::pigg::Storage;
+use crate::pigg_set::PiggSet;
+
+/// Where a path was resolved from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataSource {
+    /// A loose file on disk
+    Loose,
+    /// A file inside one of the piggs
+    Pigg,
+}
+
+/// Resolves paths the way the client does: loose files first, then piggs
+#[derive(Debug)]
+pub struct GameData<S = Mmap> {
+    loose: LooseFiles,
+    piggs: PiggSet<S>,
+}
+
+impl GameData {
+    /// Opens a client installation, using its `data` directory for loose
+    /// files and every pigg in its `piggs` directory
+    pub fn open<P: AsRef<Path>>(install_dir: P) -> Result<GameData> {
+        let install_dir = install_dir.as_ref();
+        Ok(GameData::new(
+            LooseFiles::new(install_dir.join("data")),
+            PiggSet::open_dir(install_dir.join("piggs"))?,
+        ))
+    }
+}
+
+impl<S: Storage> GameData<S> {
+    /// Layers the given loose files over the given piggs
+    pub fn new(loose: LooseFiles, piggs: PiggSet<S>) -> GameData<S> {
+        GameData { loose, piggs }
+    }
+
+    /// The loose files being layered over the piggs
+    pub fn loose(&self) -> &LooseFiles {
+        &self.loose
+    }
+
+    /// The piggs underneath the loose files
+    pub fn piggs(&self) -> &PiggSet<S> {
+        &self.piggs
+    }
+
+    /// Gets where the given path would be loaded from
+    pub fn source(&self, path: &str) -> Option<DataSource> {
+        if self.loose.contains(path) {
+            Some(DataSource::Loose)
+        } else if self.piggs.contains(path) {
+            Some(DataSource::Pigg)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the data for the given path, preferring a loose file over piggs
+    pub fn get_data(&self, path: &str) -> Result<Vec<u8>> {
+        self.get_data_cow(path).map(Cow::into_owned)
+    }
+
+    /// Gets the data for the given path, preferring a loose file over piggs,
+    /// without copying pigg data if possible
+    ///
+    /// See `Pigg::get_data_cow`
+    pub fn get_data_cow(&self, path: &str) -> Result<Cow<'_, [u8]>> {
+        match self.loose.get_data(path) {
+            Ok(data) => Ok(Cow::Owned(data)),
+            Err(Error::ItemNotFound(_)) => self.piggs.get_data_cow(path),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Whether the path exists as either a loose file or in a pigg
+    pub fn contains(&self, path: &str) -> bool {
+        self.source(path).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{Pigg, PiggFiles, PiggWriter};
+
+    /// A directory in the temp directory, removed on drop
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!(
+                "index_datamanip-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn piggs(files: &[(&str, &[u8])]) -> PiggSet<Vec<u8>> {
+        let mut writer = PiggWriter::new();
+        for &(path, data) in files {
+            writer.add(path, data, 1).unwrap();
+        }
+        let mut bytes = vec![];
+        writer.write(&mut bytes).unwrap();
+        let mut set = PiggSet::new();
+        set.add(Pigg::from_vec(bytes).unwrap());
+        set
+    }
+
+    #[test]
+    fn loose_files_take_priority_over_piggs() {
+        let dir = TempDir::new("game-data");
+        fs::create_dir_all(dir.0.join("bin")).unwrap();
+        fs::write(dir.0.join("bin").join("powers.bin"), b"loose").unwrap();
+
+        let data = GameData::new(
+            LooseFiles::new(&dir.0),
+            piggs(&[("bin/powers.bin", b"pigg"), ("bin/classes.bin", b"pigg")]),
+        );
+        assert_eq!(data.source("bin/powers.bin"), Some(DataSource::Loose));
+        assert_eq!(data.get_data("bin/powers.bin").unwrap(), b"loose");
+        assert_eq!(data.source("bin/classes.bin"), Some(DataSource::Pigg));
+        assert_eq!(data.get_data("bin/classes.bin").unwrap(), b"pigg");
+        assert_eq!(data.source("bin/missing.bin"), None);
+        assert!(matches!(
+            data.get_data("bin/missing.bin"),
+            Err(Error::ItemNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn extraction_refuses_paths_outside_the_destination() {
+        let dir = TempDir::new("extract-escape");
+        let dest = dir.0.join("out");
+        for path in ["../escaped.txt", "C:/escaped.txt"] {
+            let set = piggs(&[(path, b"escaped")]);
+            assert!(matches!(
+                set.extract_all(&dest, |_, _| true, |_| {}),
+                Err(Error::ParseError(_))
+            ));
+        }
+        assert!(!dir.0.join("escaped.txt").exists());
+
+        let set = piggs(&[("bin/powers.bin", b"powers")]);
+        assert_eq!(set.extract_all(&dest, |_, _| true, |_| {}).unwrap(), 1);
+        assert_eq!(
+            fs::read(dest.join("bin").join("powers.bin")).unwrap(),
+            b"powers"
+        );
+    }
+}
@@ -4,9 +4,28 @@
 mod common;
 pub mod defs;
 pub mod error;
+mod export;
+mod extract;
+mod game_data;
+mod listing;
+mod loose_files;
 pub mod objects;
 mod parse7;
 pub mod parse_messages;
 mod pigg;
+pub mod pigg_diff;
+mod pigg_files;
+mod pigg_set;
+mod pigg_update;
+mod pigg_writer;
 
-pub use pigg::Pigg;
+pub use export::MANIFEST_NAME;
+pub use extract::ExtractProgress;
+pub use game_data::{DataSource, GameData};
+pub use listing::DirEntry;
+pub use loose_files::LooseFiles;
+pub use pigg::{ArchiveHeader, Corruption, FileMetadata, Pigg, ReaderStorage, Storage};
+pub use pigg_files::{FileIter, PiggFiles};
+pub use pigg_set::PiggSet;
+pub use pigg_update::PiggUpdater;
+pub use pigg_writer::PiggWriter;
@@ -0,0 +1,193 @@
+//! Directory-style views over the flat list of paths stored in piggs
+
+use std::collections::BTreeMap;
+
+use glob::{MatchOptions, Pattern};
+
+use crate::error::{Error, Result};
+use crate::pigg::FileMetadata;
+
+/// An entry in a directory-style view of a pigg
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DirEntry {
+    /// A file directly inside the directory
+    File {
+        /// Name of the file, without the directory
+        name: String,
+        /// The file's metadata
+        metadata: FileMetadata,
+    },
+    /// A subdirectory, implied by files with a longer path
+    Dir {
+        /// Name of the subdirectory, without the parent directory
+        name: String,
+    },
+}
+
+impl DirEntry {
+    /// Name of the entry, without the directory containing it
+    pub fn name(&self) -> &str {
+        match self {
+            DirEntry::File { name, .. } | DirEntry::Dir { name } => name,
+        }
+    }
+
+    /// Whether the entry is a subdirectory
+    pub fn is_dir(&self) -> bool {
+        matches!(self, DirEntry::Dir { .. })
+    }
+}
+
+/// Filters the files down to those whose path matches the glob pattern
+///
+/// `*` and `?` don't match across `/`, while `**` matches any number of
+/// directories.
+pub(crate) fn find<'a, I>(
+    files: I,
+    pattern: &str,
+) -> Result<impl Iterator<Item = (&'a str, FileMetadata)>>
+where
+    I: Iterator<Item = (&'a str, FileMetadata)>,
+{
+    let pattern = Pattern::new(pattern)
+        .map_err(|err| Error::ParseError(format!("invalid pattern {}: {}", pattern, err)))?;
+    let options = MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+
+    Ok(files.filter(move |(path, _)| pattern.matches_with(path, options)))
+}
+
+/// Lists the files and subdirectories directly inside the given directory,
+/// sorted by name
+///
+/// Leading and trailing slashes are ignored, so an empty `dir` (or `/`) lists
+/// the root. Since piggs have no empty directories, a directory without any
+/// entries doesn't exist.
+pub(crate) fn read_dir<'a, I>(files: I, dir: &str) -> Result<Vec<DirEntry>>
+where
+    I: Iterator<Item = (&'a str, FileMetadata)>,
+{
+    let prefix = match dir.trim_matches('/') {
+        "" => String::new(),
+        dir => format!("{}/", dir),
+    };
+
+    let mut entries = BTreeMap::new();
+    for (path, metadata) in files {
+        let rest = match path.strip_prefix(&prefix) {
+            Some(rest) if !rest.is_empty() => rest,
+            _ => continue,
+        };
+        let entry = match rest.find('/') {
+            Some(end) => DirEntry::Dir {
+                name: rest[..end].to_string(),
+            },
+            None => DirEntry::File {
+                name: rest.to_string(),
+                metadata,
+            },
+        };
+        entries.insert(entry.name().to_string(), entry);
+    }
+
+    if entries.is_empty() {
+        return Err(Error::ItemNotFound(dir.into()));
+    }
+    Ok(entries.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATHS: &[&str] = &[
+        "bin/powers.bin",
+        "bin/classes.bin",
+        "texts/English/menu.ms",
+        "texts/English/Menus/main.ms",
+        "readme.txt",
+    ];
+
+    fn files() -> impl Iterator<Item = (&'static str, FileMetadata)> {
+        let metadata = FileMetadata {
+            size: 0,
+            pack_size: 0,
+            timestamp: 0,
+            offset: 0,
+            checksum: 0,
+        };
+        PATHS.iter().map(move |&path| (path, metadata))
+    }
+
+    fn found(pattern: &str) -> Vec<&'static str> {
+        find(files(), pattern)
+            .unwrap()
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    fn names(dir: &str) -> Vec<String> {
+        read_dir(files(), dir)
+            .unwrap()
+            .iter()
+            .map(|entry| match entry {
+                DirEntry::File { name, .. } => name.clone(),
+                DirEntry::Dir { name } => format!("{}/", name),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn find_stars_stay_within_a_directory() {
+        assert_eq!(found("bin/*.bin"), ["bin/powers.bin", "bin/classes.bin"]);
+        assert_eq!(found("*.txt"), ["readme.txt"]);
+        assert_eq!(found("texts/*.ms"), Vec::<&str>::new());
+        assert_eq!(found("texts/English/?enu.ms"), ["texts/English/menu.ms"]);
+    }
+
+    #[test]
+    fn find_double_star_crosses_directories() {
+        assert_eq!(
+            found("texts/**/*.ms"),
+            ["texts/English/menu.ms", "texts/English/Menus/main.ms"]
+        );
+        assert_eq!(found("**/main.ms"), ["texts/English/Menus/main.ms"]);
+    }
+
+    #[test]
+    fn find_rejects_invalid_patterns() {
+        assert!(matches!(
+            find(files(), "bin/[").err(),
+            Some(Error::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn read_dir_lists_direct_children() {
+        assert_eq!(names(""), ["bin/", "readme.txt", "texts/"]);
+        assert_eq!(names("texts/English"), ["Menus/", "menu.ms"]);
+    }
+
+    #[test]
+    fn read_dir_ignores_surrounding_slashes() {
+        for dir in ["/", "//"] {
+            assert_eq!(names(dir), names(""));
+        }
+        for dir in ["/bin", "bin/", "/bin/"] {
+            assert_eq!(names(dir), ["classes.bin", "powers.bin"]);
+        }
+    }
+
+    #[test]
+    fn read_dir_of_missing_or_file_paths_is_not_found() {
+        for dir in ["missing", "readme.txt", "bin/powers.bin", "bi"] {
+            assert!(matches!(
+                read_dir(files(), dir),
+                Err(Error::ItemNotFound(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::convert::AsRef;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+/// Loose files on disk, laid out the same way as the paths inside piggs
+///
+/// The client gives these priority over pigg contents, so they're used for
+/// local modifications and development builds.
+#[derive(Debug, Clone)]
+pub struct LooseFiles {
+    root: PathBuf,
+}
+
+impl LooseFiles {
+    /// Uses the given directory (typically `data/`) as the root for lookups
+    pub fn new<P: AsRef<Path>>(root: P) -> LooseFiles {
+        LooseFiles {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The directory lookups are relative to
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Gets the contents of the loose file at the given pigg-style path
+    pub fn get_data(&self, path: &str) -> Result<Vec<u8>> {
+        let full_path = self
+            .resolve(path)
+            .ok_or_else(|| Error::ItemNotFound(path.into()))?;
+        fs::read(full_path).map_err(|err| match err.kind() {
+            ErrorKind::NotFound => Error::ItemNotFound(path.into()),
+            _ => Error::IoError(err),
+        })
+    }
+
+    /// Whether a loose file exists at the given pigg-style path
+    pub fn contains(&self, path: &str) -> bool {
+        self.resolve(path).is_some_and(|path| path.is_file())
+    }
+
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        resolve_path(&self.root, path)
+    }
+}
+
+/// Converts a pigg-style path into a path under the given root
+///
+/// Paths which would escape the root aren't resolved.
+pub(crate) fn resolve_path(root: &Path, path: &str) -> Option<PathBuf> {
+    let mut full_path = root.to_path_buf();
+    for component in path.split(['/', '\\']) {
+        match component {
+            "" | "." => continue,
+            ".." => return None,
+            // Drive prefixes
+            _ if component.contains(':') => return None,
+            _ => full_path.push(component),
+        }
+    }
+    Some(full_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_path_stays_under_the_root() {
+        let root = Path::new("data");
+        assert_eq!(
+            resolve_path(root, "bin/powers.bin"),
+            Some(root.join("bin").join("powers.bin"))
+        );
+        assert_eq!(
+            resolve_path(root, "/texts\\English/./menu.ms"),
+            Some(root.join("texts").join("English").join("menu.ms"))
+        );
+    }
+
+    #[test]
+    fn resolve_path_rejects_escapes() {
+        let root = Path::new("data");
+        for path in [
+            "..",
+            "../evil.txt",
+            "bin/../../evil.txt",
+            "bin\\..\\evil.txt",
+            "C:/Windows/evil.dll",
+            "C:evil.txt",
+            "bin/D:/evil.txt",
+        ] {
+            assert_eq!(resolve_path(root, path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn escaping_paths_are_not_found() {
+        let loose = LooseFiles::new("data");
+        assert!(matches!(
+            loose.get_data("../Cargo.toml"),
+            Err(Error::ItemNotFound(_))
+        ));
+        assert!(!loose.contains("../Cargo.toml"));
+    }
+}
@@ -2,6 +2,8 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::parse_messages::MessageLookup;
+
 type LString = std::ffi::CString;
 
 #[derive(Debug, Serialize)]
@@ -48,14 +50,17 @@ pub struct Class {
 }
 
 impl Class {
-    pub fn fix_strings(&mut self, pmessages: &std::collections::HashMap<String, String>) {
+    pub fn fix_strings<M: MessageLookup + ?Sized>(&mut self, pmessages: &M) {
         // TODO: Switch to proper error handling
-        self.display_name = pmessages.get(&self.display_name).unwrap().to_string();
-        self.display_help = pmessages.get(&self.display_help).unwrap().to_string();
-        self.display_short_help = pmessages.get(&self.display_short_help).unwrap().to_string();
+        self.display_name = pmessages.lookup(&self.display_name).unwrap().to_string();
+        self.display_help = pmessages.lookup(&self.display_help).unwrap().to_string();
+        self.display_short_help = pmessages
+            .lookup(&self.display_short_help)
+            .unwrap()
+            .to_string();
         if !self.locked_tooltip.is_empty() {
             self.locked_tooltip = pmessages
-                .get(&self.locked_tooltip)
+                .lookup(&self.locked_tooltip)
                 .unwrap_or_else(|| panic!("Not found: {}", self.locked_tooltip))
                 .to_string();
         }
@@ -317,16 +322,19 @@ pub struct PowerCategory {
 }
 
 impl PowerCategory {
-    pub fn fix_strings(&mut self, pmessages: &std::collections::HashMap<String, String>) {
+    pub fn fix_strings<M: MessageLookup + ?Sized>(&mut self, pmessages: &M) {
         // TODO: Switch to proper error handling
         if !self.display_name.is_empty() {
-            self.display_name = pmessages.get(&self.display_name).unwrap().to_string();
+            self.display_name = pmessages.lookup(&self.display_name).unwrap().to_string();
         }
         if !self.display_help.is_empty() {
-            self.display_help = pmessages.get(&self.display_help).unwrap().to_string();
+            self.display_help = pmessages.lookup(&self.display_help).unwrap().to_string();
         }
         if !self.display_short_help.is_empty() {
-            self.display_short_help = pmessages.get(&self.display_short_help).unwrap().to_string();
+            self.display_short_help = pmessages
+                .lookup(&self.display_short_help)
+                .unwrap()
+                .to_string();
         }
     }
 }
@@ -537,11 +545,14 @@ pub struct Power {
 }
 
 impl Power {
-    pub fn fix_strings(&mut self, pmessages: &std::collections::HashMap<String, String>) {
+    pub fn fix_strings<M: MessageLookup + ?Sized>(&mut self, pmessages: &M) {
         // TODO: Switch to proper error handling
-        self.display_name = pmessages.get(&self.display_name).unwrap().to_string();
-        self.display_help = pmessages.get(&self.display_help).unwrap().to_string();
-        self.display_short_help = pmessages.get(&self.display_short_help).unwrap().to_string();
+        self.display_name = pmessages.lookup(&self.display_name).unwrap().to_string();
+        self.display_help = pmessages.lookup(&self.display_help).unwrap().to_string();
+        self.display_short_help = pmessages
+            .lookup(&self.display_short_help)
+            .unwrap()
+            .to_string();
     }
 }
 
@@ -741,11 +752,14 @@ pub struct Powerset {
 }
 
 impl Powerset {
-    pub fn fix_strings(&mut self, pmessages: &std::collections::HashMap<String, String>) {
+    pub fn fix_strings<M: MessageLookup + ?Sized>(&mut self, pmessages: &M) {
         // TODO: Switch to proper error handling
-        self.display_name = pmessages.get(&self.display_name).unwrap().to_string();
-        self.display_help = pmessages.get(&self.display_help).unwrap().to_string();
-        self.display_short_help = pmessages.get(&self.display_short_help).unwrap().to_string();
+        self.display_name = pmessages.lookup(&self.display_name).unwrap().to_string();
+        self.display_help = pmessages.lookup(&self.display_help).unwrap().to_string();
+        self.display_short_help = pmessages
+            .lookup(&self.display_short_help)
+            .unwrap()
+            .to_string();
     }
 }
 
@@ -857,9 +871,9 @@ impl std::convert::From<u32> for AttackType {
     }
 }
 
-impl std::convert::Into<u32> for AttackType {
-    fn into(self) -> u32 {
-        match self {
+impl std::convert::From<AttackType> for u32 {
+    fn from(attack_type: AttackType) -> Self {
+        match attack_type {
             AttackType::Ranged => 104,
             AttackType::Melee => 108,
             AttackType::AOE => 112,
@@ -1,12 +1,19 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::str;
 
 use nom::{bytes::complete::*, number::complete::*, IResult};
 use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
 
-use crate::common::parse_lstring;
+use crate::common::{parse_lstring, write_lstring, StringTable};
 use crate::error::{Error, Result};
 
+/// Structs whose definitions are known to stop short of the data, so extra
+/// bytes after their fields are ignored when parsing them, and writing them
+/// would lose data
+const INCOMPLETE_STRUCTS: &[&str] = &["Class"];
+
 /// Parses a byte buffer and string mapping into the given type T
 pub fn parse<T: DeserializeOwned>(input: &[u8], strings: &HashMap<u32, String>) -> Result<T> {
     let mut deserializer = Deserializer::from_bytes(input, strings);
@@ -36,7 +43,7 @@ impl<'de> Deserializer<'de> {
     }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     type Error = Error;
 
     fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
@@ -205,7 +212,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         unimplemented!()
     }
 
-    fn deserialize_seq<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
@@ -223,7 +230,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
             len: usize,
         }
 
-        impl<'de, 'a, 'b: 'a> serde::de::SeqAccess<'de> for Access<'a, 'de> {
+        impl<'de> serde::de::SeqAccess<'de> for Access<'_, 'de> {
             type Error = Error;
 
             fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
@@ -283,8 +290,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         let (new_input, chunk) = take(len)(new_input)?;
         self.input = chunk;
         let ret = self.deserialize_tuple(fields.len(), visitor);
-        // Class is known to be incomplete, so ignore those for now
-        if name != "Class" && !self.input.is_empty() {
+        if !INCOMPLETE_STRUCTS.contains(&name) && !self.input.is_empty() {
             eprintln!(
                 "WARNING: {} had {} extra bytes at the end: {:?}",
                 name,
@@ -305,22 +311,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        impl<'de, 'a> serde::de::EnumAccess<'de> for &'a mut Deserializer<'de> {
-            type Error = crate::parse7::Error;
-            type Variant = Self;
-
-            fn variant_seed<V: serde::de::DeserializeSeed<'de>>(
-                self,
-                seed: V,
-            ) -> std::result::Result<(V::Value, Self), Error> {
-                let idx: u32 = serde::de::Deserialize::deserialize(&mut *self)?;
-                let moo: serde::de::value::U32Deserializer<Error> = idx.into_deserializer();
-                let v = serde::de::DeserializeSeed::deserialize(seed, moo)?;
-                Ok((v, self))
-            }
-        }
-
-        Ok(visitor.visit_enum(self)?)
+        visitor.visit_enum(self)
     }
 
     fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value>
@@ -338,7 +329,22 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     }
 }
 
-impl<'de, 'a> serde::de::VariantAccess<'de> for &'a mut Deserializer<'de> {
+impl<'de> serde::de::EnumAccess<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: serde::de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> std::result::Result<(V::Value, Self), Error> {
+        let idx: u32 = serde::de::Deserialize::deserialize(&mut *self)?;
+        let moo: serde::de::value::U32Deserializer<Error> = idx.into_deserializer();
+        let v = serde::de::DeserializeSeed::deserialize(seed, moo)?;
+        Ok((v, self))
+    }
+}
+
+impl<'de> serde::de::VariantAccess<'de> for &mut Deserializer<'de> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<()> {
@@ -363,3 +369,278 @@ impl<'de, 'a> serde::de::VariantAccess<'de> for &'a mut Deserializer<'de> {
         unimplemented!()
     }
 }
+
+/// Serializes T into a byte buffer, adding its strings to the given table
+pub fn serialize<T: Serialize>(value: &T, strings: &mut StringTable) -> Result<Vec<u8>> {
+    let mut serializer = Serializer {
+        output: vec![],
+        strings,
+        lengths: vec![],
+    };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.output)
+}
+
+pub struct Serializer<'a> {
+    output: Vec<u8>,
+    strings: &'a mut StringTable,
+    /// Where each struct still being written has its length field
+    lengths: Vec<usize>,
+}
+
+impl Serializer<'_> {
+    fn write_u32(&mut self, value: u32) {
+        self.output.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn unsupported(what: &str) -> Error {
+        Error::EncodeError(format!("{} can't be written to Parse7", what))
+    }
+}
+
+impl ser::Serializer for &mut Serializer<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = ser::Impossible<(), Error>;
+    type SerializeMap = ser::Impossible<(), Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = ser::Impossible<(), Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.write_u32(v as u32);
+        Ok(())
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<()> {
+        Err(Serializer::unsupported("i8"))
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<()> {
+        Err(Serializer::unsupported("i16"))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<()> {
+        Err(Serializer::unsupported("i64"))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.write_u32(v.into());
+        Ok(())
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<()> {
+        Err(Serializer::unsupported("u16"))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.write_u32(v);
+        Ok(())
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<()> {
+        Err(Serializer::unsupported("u64"))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<()> {
+        Err(Serializer::unsupported("f64"))
+    }
+
+    fn serialize_char(self, _v: char) -> Result<()> {
+        Err(Serializer::unsupported("char"))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        let offset = self.strings.add(v)?.offset;
+        self.write_u32(offset);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        write_lstring(&mut self.output, v)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.write_u32(0);
+        Ok(())
+    }
+
+    // The deserializer only peeks at the leading u32 to tell None apart, so
+    // Some is written as the value alone, which mustn't start with a zero
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<()> {
+        let start = self.output.len();
+        value.serialize(&mut *self)?;
+        match self.output.get(start..start + 4) {
+            Some(word) if word != [0; 4] => Ok(()),
+            _ => Err(Error::EncodeError(
+                "Some value would read back as None, as it starts with a zero".into(),
+            )),
+        }
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Err(Serializer::unsupported("()"))
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<()> {
+        Err(Serializer::unsupported(name))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<()> {
+        self.write_u32(variant_index);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        _value: &T,
+    ) -> Result<()> {
+        Err(Serializer::unsupported(name))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.write_u32(variant_index);
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        let len = len.ok_or_else(|| Serializer::unsupported("a sequence of unknown length"))?;
+        let len = u32::try_from(len).map_err(|_| Serializer::unsupported("a huge sequence"))?;
+        self.write_u32(len);
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(Serializer::unsupported(name))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(Serializer::unsupported("a map"))
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        if INCOMPLETE_STRUCTS.contains(&name) {
+            return Err(Error::EncodeError(format!(
+                "{} doesn't define every field, so can't be written back",
+                name
+            )));
+        }
+        // Filled in once the struct's fields have been written
+        self.lengths.push(self.output.len());
+        self.write_u32(0);
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(Serializer::unsupported(name))
+    }
+}
+
+impl ser::SerializeSeq for &mut Serializer<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut Serializer<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut Serializer<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut Serializer<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        let start = self.lengths.pop().expect("struct ended without starting");
+        let len = u32::try_from(self.output.len() - start - 4)
+            .map_err(|_| Serializer::unsupported("a huge struct"))?;
+        self.output[start..start + 4].copy_from_slice(&len.to_le_bytes());
+        Ok(())
+    }
+}
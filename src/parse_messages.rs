@@ -1,89 +1,798 @@
-//! MessageStore decoder
+//! MessageStore decoder and encoder
 
-use std::collections::HashMap;
-use std::fmt::Debug;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::AsRef;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 
 use nom::bytes::complete::*;
 use nom::number::complete::*;
 
+use crate::common::{to_u32, write_u32, StringTable};
 use crate::error::{Error, Result};
 
+/// Version of the MessageStore layout this module understands
+const MS_VERSION: u32 = 20_090_521;
+/// `help_index` written for messages without any help text
+const NO_HELP: u32 = u32::MAX;
+
 /// Gets P-string -> display string mapping
 ///
-/// This is a really simplistic interpretation of the MessageStore format, and
-/// doesn't support most of the functionality, but is enough for handling the
-/// power related data.
+/// This only keeps each message's text. See `MessageStore` for the help
+/// strings and variables.
 pub fn get_pmessages(input: &[u8]) -> Result<HashMap<String, String>> {
-    let (input, _) = validate_header(input)?;
-    let (input, messages) = parse_strings_list(input)?;
-    // We don't currently support variables in strings
-    let (input, _variables) = parse_strings_list(input)?;
-    let (input, stash) = parse_stash(input)?;
+    Ok(MessageStore::parse(input)?.to_pmessages())
+}
+
+/// A parsed MessageStore, mapping message IDs such as `P12345` to their
+/// display text
+///
+/// Messages and help strings share a single string table, while the
+/// variables referenced by each message's attributes live in a second table.
+#[derive(Debug, Default)]
+pub struct MessageStore {
+    messages: Vec<String>,
+    variables: Vec<String>,
+    stash: Vec<StashItem>,
+    /// Maps each message ID to its position in `stash`
+    ids: HashMap<String, usize>,
+}
+
+/// A single entry in a MessageStore's stash, exactly as stored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StashItem {
+    /// The message's ID, such as `P12345`
+    pub message_id: String,
+    /// Index of the message's text in the messages table
+    pub message_index: u32,
+    /// Index of the message's help text in the messages table
+    pub help_index: u32,
+    /// Indices of the message's variables in the variables table
+    pub attributes: Vec<u32>,
+}
+
+/// A message with its text, help, and variables looked up
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message<'a> {
+    /// The message's ID, such as `P12345`
+    pub id: &'a str,
+    /// The display text
+    pub text: &'a str,
+    /// The help text, if the message has any
+    pub help: Option<&'a str>,
+    /// The variables the message references, in attribute order
+    ///
+    /// Attributes pointing outside of the variables table are skipped.
+    pub variables: Vec<&'a str>,
+}
+
+/// A value substituted into a message by `MessageStore::format`
+#[derive(Clone, Debug, PartialEq)]
+pub enum FormatArg {
+    /// A whole number, such as a count
+    Int(i64),
+    /// A fractional number, such as a magnitude
+    Float(f64),
+    /// Text, such as a name
+    Str(String),
+}
+
+impl fmt::Display for FormatArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatArg::Int(i) => write!(f, "{}", i),
+            FormatArg::Float(x) => write!(f, "{}", x),
+            FormatArg::Str(s) => f.write_str(s),
+        }
+    }
+}
 
-    let mut pmessages = HashMap::new();
-    for item in stash {
-        let target = messages
-            .get(item.message_index as usize)
-            .ok_or_else(|| Error::ParseError(format!("{} out of range", item.message_index)))?;
-        pmessages.insert(item.message_id, target.to_owned());
+impl FormatArg {
+    /// Writes the argument for a variable defined with the given type
+    ///
+    /// `int` rounds floats to whole numbers, `float` writes whole numbers
+    /// as-is, and both reject text. `string` and any other or missing type
+    /// take any argument as-is.
+    fn format_as(&self, kind: Option<&str>) -> std::result::Result<String, String> {
+        let kind = kind.map(str::to_ascii_lowercase);
+        match (kind.as_deref(), self) {
+            (Some("int"), FormatArg::Float(f)) => Ok(format!("{}", f.round())),
+            (Some("int"), FormatArg::Str(_)) | (Some("float"), FormatArg::Str(_)) => {
+                Err(format!("expected a number, found {:?}", self))
+            }
+            _ => Ok(self.to_string()),
+        }
     }
+}
 
-    // TODO: Return an error instead
-    assert!(input.is_empty(), "Data unexpectedly found at end of file");
+impl From<i32> for FormatArg {
+    fn from(i: i32) -> Self {
+        FormatArg::Int(i.into())
+    }
+}
 
-    Ok(pmessages)
+impl From<u32> for FormatArg {
+    fn from(i: u32) -> Self {
+        FormatArg::Int(i.into())
+    }
 }
 
-fn validate_header(input: &[u8]) -> Result<(&[u8], ())> {
-    let (input, version) = le_u32(input)?;
-    // TODO: Make it return an error instead
-    assert!(
-        version == 20_090_521,
-        "Unrecognized ms version: {}",
-        version
-    );
-    Ok((input, ()))
+impl From<i64> for FormatArg {
+    fn from(i: i64) -> Self {
+        FormatArg::Int(i)
+    }
 }
 
-#[derive(Debug)]
-struct StashItem {
-    message_id: String,
-    message_index: u32,
-    help_index: u32,
-    attributes: Vec<u32>,
+impl From<f32> for FormatArg {
+    fn from(f: f32) -> Self {
+        // Widening directly turns 0.3 into 0.30000001192092896, so go via the
+        // shortest string which reads back as the same f32
+        FormatArg::Float(f.to_string().parse().unwrap_or_else(|_| f.into()))
+    }
 }
 
-fn parse_stash(input: &[u8]) -> Result<(&[u8], Vec<StashItem>)> {
-    let (mut input, _count) = le_u32(input)?;
+impl From<f64> for FormatArg {
+    fn from(f: f64) -> Self {
+        FormatArg::Float(f)
+    }
+}
 
-    // let mut input = input;
-    let mut stash = vec![];
-    while !input.is_empty() {
-        let (new_input, str_len) = le_u32(input)?;
-        let (new_input, s) = take(str_len)(new_input)?;
+impl From<&str> for FormatArg {
+    fn from(s: &str) -> Self {
+        FormatArg::Str(s.to_string())
+    }
+}
 
-        let (new_input, message_index) = le_u32(new_input)?;
-        let (new_input, help_index) = le_u32(new_input)?;
-        let (new_input, var_count) = le_u32(new_input)?;
+impl From<String> for FormatArg {
+    fn from(s: String) -> Self {
+        FormatArg::Str(s)
+    }
+}
+
+/// How `MessageStore::parse_with` treats malformed data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fail on the first problem
+    Strict,
+    /// Collect a warning for each problem and keep whatever could be read
+    ///
+    /// An unrecognized version or trailing data is ignored, messages pointing
+    /// outside of the messages table are skipped, and a truncated stash keeps
+    /// the messages before the truncation.
+    Lenient,
+}
+
+/// Tracks where parsing is up to, so problems can be reported with an offset
+struct Context {
+    len: usize,
+    version: u32,
+    mode: ParseMode,
+    warnings: Vec<Error>,
+}
+
+impl Context {
+    fn error(&self, input: &[u8], reason: impl fmt::Display) -> Error {
+        Error::MessageStoreError {
+            version: self.version,
+            offset: self.len - input.len(),
+            reason: reason.to_string(),
+        }
+    }
 
-        let mut attributes = vec![];
-        let mut new_input = new_input;
-        for _ in 0..var_count {
-            let (new_input2, var) = le_u32(new_input)?;
-            new_input = new_input2;
-            attributes.push(var);
+    /// Fails in strict mode, and otherwise records a warning and lets parsing
+    /// continue
+    fn problem(&mut self, input: &[u8], reason: impl fmt::Display) -> Result<()> {
+        let err = self.error(input, reason);
+        match self.mode {
+            ParseMode::Strict => Err(err),
+            ParseMode::Lenient => {
+                self.warnings.push(err);
+                Ok(())
+            }
         }
+    }
+}
 
-        stash.push(StashItem {
-            message_id: String::from_utf8_lossy(s).to_string(),
+impl MessageStore {
+    /// Parses a binary MessageStore, failing on any malformed data
+    pub fn parse(input: &[u8]) -> Result<MessageStore> {
+        let (store, _) = MessageStore::parse_with(input, ParseMode::Strict)?;
+        Ok(store)
+    }
+
+    /// Parses a binary MessageStore, treating malformed data as `mode` says
+    ///
+    /// Along with the store, returns a `MessageStoreError` for each problem
+    /// lenient mode skipped over, which is always empty in strict mode.
+    pub fn parse_with(input: &[u8], mode: ParseMode) -> Result<(MessageStore, Vec<Error>)> {
+        let mut ctx = Context {
+            len: input.len(),
+            version: 0,
+            mode,
+            warnings: vec![],
+        };
+
+        let (rest, version) = parse_u32(input).map_err(|_| ctx.error(input, "missing version"))?;
+        ctx.version = version;
+        if version != MS_VERSION {
+            ctx.problem(input, format!("expected version {}", MS_VERSION))?;
+        }
+        let input = rest;
+
+        let (input, messages) =
+            parse_strings_list(input).map_err(|e| ctx.error(input, format!("messages: {}", e)))?;
+        let (input, variables) =
+            parse_strings_list(input).map_err(|e| ctx.error(input, format!("variables: {}", e)))?;
+
+        let (mut input, count) =
+            parse_u32(input).map_err(|_| ctx.error(input, "missing stash count"))?;
+        let mut stash = vec![];
+        let mut ids = HashMap::new();
+        for _ in 0..count {
+            let (new_input, item) = match parse_stash_item(input) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    ctx.problem(input, format!("stash: {}", e))?;
+                    input = &[];
+                    break;
+                }
+            };
+            if item.message_index as usize >= messages.len() {
+                ctx.problem(
+                    input,
+                    format!(
+                        "{} points to message {} of {}",
+                        item.message_id,
+                        item.message_index,
+                        messages.len()
+                    ),
+                )?;
+            } else {
+                ids.insert(item.message_id.clone(), stash.len());
+                stash.push(item);
+            }
+            input = new_input;
+        }
+
+        if !input.is_empty() {
+            ctx.problem(
+                input,
+                format!("{} bytes unexpectedly found at end of file", input.len()),
+            )?;
+        }
+
+        let store = MessageStore {
+            messages,
+            variables,
+            stash,
+            ids,
+        };
+        Ok((store, ctx.warnings))
+    }
+
+    /// Parses the plain-text source form of a MessageStore
+    ///
+    /// Each line holds a quoted message ID, its text, and optionally its help
+    /// text, such as `"P12345" "Display text" "Help text"`, separated by
+    /// whitespace or commas. Quoted strings understand `\"`, `\\`, `\n`, and
+    /// `\t` escapes. Outside of strings, `#` and `//` start a comment. Blank
+    /// lines are ignored, and later lines replace earlier ones with the same
+    /// ID.
+    ///
+    /// Each message's variables are taken from the `{Variable}` placeholders
+    /// in its text, in the order they first appear.
+    pub fn parse_text(input: &str) -> Result<MessageStore> {
+        let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+
+        let mut store = MessageStore::new();
+        for (index, line) in input.lines().enumerate() {
+            let tokens = tokenize_line(line)
+                .map_err(|e| Error::ParseError(format!("line {}: {}", index + 1, e)))?;
+            match &tokens[..] {
+                [] => {}
+                [id, text] => store.insert(id, text, None, &placeholder_names(text)),
+                [id, text, help] => store.insert(id, text, Some(help), &placeholder_names(text)),
+                _ => {
+                    return Err(Error::ParseError(format!(
+                        "line {}: expected an ID, text, and optional help, found {} strings",
+                        index + 1,
+                        tokens.len()
+                    )))
+                }
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Creates an empty store
+    pub fn new() -> MessageStore {
+        MessageStore::default()
+    }
+
+    /// Adds a message, replacing any message previously stored under the
+    /// same ID
+    ///
+    /// `variables` lists the variables the message's placeholders refer to.
+    pub fn insert(&mut self, id: &str, text: &str, help: Option<&str>, variables: &[&str]) {
+        let message_index = self.messages.len() as u32;
+        self.messages.push(text.to_string());
+        let help_index = match help {
+            Some(help) => {
+                self.messages.push(help.to_string());
+                message_index + 1
+            }
+            None => NO_HELP,
+        };
+        let attributes = variables
+            .iter()
+            .map(|variable| {
+                self.variables.push(variable.to_string());
+                self.variables.len() as u32 - 1
+            })
+            .collect();
+
+        let item = StashItem {
+            message_id: id.to_string(),
             message_index,
             help_index,
             attributes,
-        });
+        };
+        match self.ids.get(id) {
+            Some(&index) => self.stash[index] = item,
+            None => {
+                self.ids.insert(id.to_string(), self.stash.len());
+                self.stash.push(item);
+            }
+        }
+    }
+
+    /// Writes the store out to the given file, replacing it if it exists
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Writes the store out in the binary layout `parse` reads
+    ///
+    /// Both string tables are rebuilt from the messages, so each distinct
+    /// string is only written once and strings no message uses are dropped.
+    pub fn write<W: Write>(&self, mut out: W) -> Result<()> {
+        let mut messages = StringTable::default();
+        let mut variables = StringTable::default();
+        let mut stash = vec![];
+        for message in self.iter() {
+            let message_index = messages.add(message.text)?.index;
+            let help_index = match message.help {
+                Some(help) => messages.add(help)?.index,
+                None => NO_HELP,
+            };
+            let attributes = message
+                .variables
+                .iter()
+                .map(|variable| Ok(variables.add(variable)?.index))
+                .collect::<Result<Vec<_>>>()?;
+            stash.push((message.id, message_index, help_index, attributes));
+        }
+
+        write_u32(&mut out, MS_VERSION)?;
+        write_strings_list(&mut out, &messages)?;
+        write_strings_list(&mut out, &variables)?;
+
+        write_u32(&mut out, to_u32(stash.len(), "stash")?)?;
+        for (id, message_index, help_index, attributes) in stash {
+            write_u32(&mut out, to_u32(id.len(), id)?)?;
+            out.write_all(id.as_bytes())?;
+            write_u32(&mut out, message_index)?;
+            write_u32(&mut out, help_index)?;
+            write_u32(&mut out, to_u32(attributes.len(), id)?)?;
+            for attribute in attributes {
+                write_u32(&mut out, attribute)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the given message ID
+    pub fn get(&self, id: &str) -> Option<Message<'_>> {
+        self.stash_item(id).map(|item| self.resolve(item))
+    }
+
+    /// Gets the display text for the given message ID
+    pub fn text(&self, id: &str) -> Option<&str> {
+        self.get(id).map(|message| message.text)
+    }
+
+    /// Gets the help text for the given message ID, if it has any
+    pub fn help(&self, id: &str) -> Option<&str> {
+        self.get(id)?.help
+    }
+
+    /// Gets the raw stash entry for the given message ID
+    pub fn stash_item(&self, id: &str) -> Option<&StashItem> {
+        self.ids.get(id).map(|&index| &self.stash[index])
+    }
+
+    /// Iterates over every message, in the order they're stored
+    pub fn iter(&self) -> impl Iterator<Item = Message<'_>> {
+        self.stash.iter().map(move |item| self.resolve(item))
+    }
+
+    /// The raw stash entries, in the order they're stored
+    pub fn stash(&self) -> &[StashItem] {
+        &self.stash
+    }
+
+    /// The table holding every message and help string
+    pub fn messages(&self) -> &[String] {
+        &self.messages
+    }
+
+    /// The table of variables referenced by the messages' attributes
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Formats the given message, substituting its `{Variable}` placeholders
+    /// with the matching named arguments
+    ///
+    /// Only the message's own variable definitions are substituted, and each
+    /// of them must have an argument. A definition is a name, optionally
+    /// followed by whitespace and a type of `int`, `float`, or `string`,
+    /// which decides how its argument is written. Any other braced text is
+    /// left as-is.
+    pub fn format(&self, id: &str, args: &[(&str, FormatArg)]) -> Result<String> {
+        let message = self.get(id).ok_or_else(|| Error::ItemNotFound(id.into()))?;
+
+        let mut values = HashMap::new();
+        for definition in &message.variables {
+            let (name, kind) = parse_definition(definition);
+            let (_, arg) = args
+                .iter()
+                .find(|(arg, _)| *arg == name)
+                .ok_or_else(|| Error::ItemNotFound(format!("{{{}}} argument for {}", name, id)))?;
+            let value = arg
+                .format_as(kind)
+                .map_err(|e| Error::EncodeError(format!("{{{}}} in {}: {}", name, id, e)))?;
+            values.insert(name, value);
+        }
+        if let Some((name, _)) = args.iter().find(|(name, _)| !values.contains_key(name)) {
+            return Err(Error::ItemNotFound(format!(
+                "{{{}}} variable in {}",
+                name, id
+            )));
+        }
+
+        let mut out = String::with_capacity(message.text.len());
+        let mut rest = message.text;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let end = match after.find(['{', '}']) {
+                Some(end) if after.as_bytes()[end] == b'}' => end,
+                _ => {
+                    out.push('{');
+                    rest = after;
+                    continue;
+                }
+            };
+
+            let placeholder = &after[..end];
+            match values.get(placeholder) {
+                Some(value) => out.push_str(value),
+                None => {
+                    out.push('{');
+                    out.push_str(placeholder);
+                    out.push('}');
+                }
+            }
+            rest = &after[end + 1..];
+        }
+        out.push_str(rest);
+
+        Ok(out)
+    }
+
+    /// Gets P-string -> display string mapping, as `get_pmessages` does
+    pub fn to_pmessages(&self) -> HashMap<String, String> {
+        self.iter()
+            .map(|message| (message.id.to_string(), message.text.to_string()))
+            .collect()
+    }
+
+    /// Number of messages in the store
+    pub fn len(&self) -> usize {
+        self.stash.len()
+    }
+
+    /// Whether the store holds no messages
+    pub fn is_empty(&self) -> bool {
+        self.stash.is_empty()
+    }
+
+    /// Whether the store holds the given message ID
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.contains_key(id)
+    }
+
+    fn resolve<'a>(&'a self, item: &'a StashItem) -> Message<'a> {
+        Message {
+            id: &item.message_id,
+            // Checked when parsing
+            text: &self.messages[item.message_index as usize],
+            help: self
+                .messages
+                .get(item.help_index as usize)
+                .map(String::as_str),
+            variables: item
+                .attributes
+                .iter()
+                .filter_map(|&index| self.variables.get(index as usize))
+                .map(String::as_str)
+                .collect(),
+        }
+    }
+}
+
+/// Compiles the plain-text source form of a MessageStore into the binary
+/// form
+///
+/// See `MessageStore::parse_text` and `MessageStore::write`
+pub fn compile_text<W: Write>(input: &str, out: W) -> Result<()> {
+    MessageStore::parse_text(input)?.write(out)
+}
+
+/// Splits a line of message source into its quoted strings
+fn tokenize_line(line: &str) -> std::result::Result<Vec<String>, String> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() || c == ',' => {}
+            '#' => break,
+            '/' if chars.peek() == Some(&'/') => break,
+            '"' => {
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => token.push('\n'),
+                            Some('t') => token.push('\t'),
+                            Some(c @ '"') | Some(c @ '\\') => token.push(c),
+                            Some(c) => return Err(format!("unknown escape \\{}", c)),
+                            None => return Err("unterminated string".into()),
+                        },
+                        Some(c) => token.push(c),
+                        None => return Err("unterminated string".into()),
+                    }
+                }
+                tokens.push(token);
+            }
+            c => return Err(format!("unexpected {:?} outside of a string", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Splits a variable definition into the name it's substituted by and its
+/// type, if it has one
+fn parse_definition(definition: &str) -> (&str, Option<&str>) {
+    let mut parts = definition.split_whitespace();
+    (parts.next().unwrap_or_default(), parts.next())
+}
+
+/// Lists the variables named by a message's placeholders, in the order they
+/// first appear
+fn placeholder_names(text: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        if let Some(end) = rest.find(['{', '}']) {
+            if rest.as_bytes()[end] == b'}' {
+                let name = &rest[..end];
+                if !name.is_empty() && !names.contains(&name) {
+                    names.push(name);
+                }
+                rest = &rest[end + 1..];
+            }
+        }
+    }
+    names
+}
+
+/// Anything message IDs can be looked up in, such as the map returned by
+/// `get_pmessages`, a `MessageStore`, or one locale of a `LocalizedMessages`
+pub trait MessageLookup {
+    /// Gets the display text for the given message ID
+    fn lookup(&self, id: &str) -> Option<&str>;
+}
+
+impl MessageLookup for HashMap<String, String> {
+    fn lookup(&self, id: &str) -> Option<&str> {
+        self.get(id).map(String::as_str)
+    }
+}
+
+impl MessageLookup for MessageStore {
+    fn lookup(&self, id: &str) -> Option<&str> {
+        self.text(id)
+    }
+}
+
+/// Several MessageStores loaded side by side, one per locale, such as `en`,
+/// `fr`, and `de`
+///
+/// Lookups in a locale fall back to the default locale for any message the
+/// locale doesn't have.
+#[derive(Debug)]
+pub struct LocalizedMessages {
+    default_locale: String,
+    stores: BTreeMap<String, MessageStore>,
+}
+
+/// The message IDs a locale is missing or has in excess, compared to the
+/// default locale
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocaleReport<'a> {
+    /// The locale being compared
+    pub locale: &'a str,
+    /// IDs the default locale has but this one doesn't, sorted
+    pub missing: Vec<&'a str>,
+    /// IDs this locale has but the default one doesn't, sorted
+    pub extra: Vec<&'a str>,
+}
+
+impl LocalizedMessages {
+    /// Creates an empty set of locales, falling back to `default_locale`
+    pub fn new(default_locale: &str) -> LocalizedMessages {
+        LocalizedMessages {
+            default_locale: default_locale.to_string(),
+            stores: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the messages for a locale, replacing any previously added
+    pub fn insert(&mut self, locale: &str, store: MessageStore) {
+        self.stores.insert(locale.to_string(), store);
+    }
+
+    /// The locale lookups fall back to
+    pub fn default_locale(&self) -> &str {
+        &self.default_locale
+    }
+
+    /// Iterates over every locale added, sorted by name
+    pub fn locales(&self) -> impl Iterator<Item = &str> {
+        self.stores.keys().map(String::as_str)
+    }
+
+    /// Gets the messages for a single locale, without any fallback
+    pub fn store(&self, locale: &str) -> Option<&MessageStore> {
+        self.stores.get(locale)
+    }
+
+    /// Looks up the given message ID in a locale, falling back to the
+    /// default locale
+    pub fn get(&self, locale: &str, id: &str) -> Option<Message<'_>> {
+        self.store_for(locale, id)?.get(id)
+    }
+
+    /// Gets the display text for the given message ID in a locale, falling
+    /// back to the default locale
+    pub fn text(&self, locale: &str, id: &str) -> Option<&str> {
+        self.get(locale, id).map(|message| message.text)
+    }
+
+    /// Formats the given message in a locale, falling back to the default
+    /// locale
+    ///
+    /// See `MessageStore::format`
+    pub fn format(&self, locale: &str, id: &str, args: &[(&str, FormatArg)]) -> Result<String> {
+        self.store_for(locale, id)
+            .ok_or_else(|| Error::ItemNotFound(id.into()))?
+            .format(id, args)
+    }
+
+    /// A view of a single locale, with fallback, for use with `fix_strings`
+    pub fn locale<'a>(&'a self, locale: &'a str) -> Locale<'a> {
+        Locale {
+            messages: self,
+            locale,
+        }
+    }
+
+    /// Compares every other locale against the default locale
+    ///
+    /// Locales are listed in name order. Every ID is reported as extra if the
+    /// default locale hasn't been added.
+    pub fn report(&self) -> Vec<LocaleReport<'_>> {
+        let default = self.stores.get(&self.default_locale);
+        let has = |store: Option<&MessageStore>, id: &str| store.is_some_and(|s| s.contains(id));
+
+        self.stores
+            .iter()
+            .filter(|(locale, _)| **locale != self.default_locale)
+            .map(|(locale, store)| {
+                let mut missing: Vec<_> = default
+                    .into_iter()
+                    .flat_map(|default| default.iter())
+                    .map(|message| message.id)
+                    .filter(|id| !store.contains(id))
+                    .collect();
+                let mut extra: Vec<_> = store
+                    .iter()
+                    .map(|message| message.id)
+                    .filter(|id| !has(default, id))
+                    .collect();
+                missing.sort_unstable();
+                extra.sort_unstable();
+                LocaleReport {
+                    locale,
+                    missing,
+                    extra,
+                }
+            })
+            .collect()
+    }
+
+    /// Picks the store holding the given ID, preferring the requested locale
+    fn store_for(&self, locale: &str, id: &str) -> Option<&MessageStore> {
+        self.stores
+            .get(locale)
+            .filter(|store| store.contains(id))
+            .or_else(|| self.stores.get(&self.default_locale))
+    }
+}
+
+/// A single locale of a `LocalizedMessages`, falling back to the default
+/// locale
+#[derive(Clone, Copy, Debug)]
+pub struct Locale<'a> {
+    messages: &'a LocalizedMessages,
+    locale: &'a str,
+}
+
+impl MessageLookup for Locale<'_> {
+    fn lookup(&self, id: &str) -> Option<&str> {
+        self.messages.text(self.locale, id)
+    }
+}
+
+fn parse_u32(input: &[u8]) -> Result<(&[u8], u32)> {
+    Ok(le_u32(input)?)
+}
+
+fn parse_stash_item(input: &[u8]) -> Result<(&[u8], StashItem)> {
+    let (input, str_len) = le_u32(input)?;
+    let (input, s) = take(str_len)(input)?;
+
+    let (input, message_index) = le_u32(input)?;
+    let (input, help_index) = le_u32(input)?;
+    let (mut input, var_count) = le_u32(input)?;
+
+    let mut attributes = vec![];
+    for _ in 0..var_count {
+        let (new_input, var) = le_u32(input)?;
         input = new_input;
+        attributes.push(var);
     }
 
-    Ok((input, stash))
+    Ok((
+        input,
+        StashItem {
+            message_id: String::from_utf8_lossy(s).to_string(),
+            message_index,
+            help_index,
+            attributes,
+        },
+    ))
 }
 
 fn parse_strings_list(input: &[u8]) -> Result<(&[u8], Vec<String>)> {
@@ -107,3 +816,303 @@ fn parse_strings_list(input: &[u8]) -> Result<(&[u8], Vec<String>)> {
 fn is_nonnull(c: u8) -> bool {
     c != 0
 }
+
+/// Writes a string table in the layout `parse_strings_list` reads
+fn write_strings_list<W: Write>(out: &mut W, strings: &StringTable) -> Result<()> {
+    write_u32(out, to_u32(strings.len(), "string table")?)?;
+    write_u32(out, to_u32(strings.as_bytes().len(), "string table")?)?;
+    out.write_all(strings.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(store: &MessageStore) -> Vec<u8> {
+        let mut bytes = vec![];
+        store.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn written_store_parses_back() {
+        let mut store = MessageStore::new();
+        store.insert("P1", "Fire Blast", Some("Deals fire damage"), &[]);
+        store.insert(
+            "P2",
+            "{Damage} damage to {Target}",
+            None,
+            &["Damage", "Target"],
+        );
+        // Shares its text and a variable with the messages above
+        store.insert("P3", "Fire Blast", None, &["Target"]);
+
+        let parsed = MessageStore::parse(&written(&store)).unwrap();
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed.text("P1"), Some("Fire Blast"));
+        assert_eq!(parsed.help("P1"), Some("Deals fire damage"));
+        assert_eq!(parsed.help("P2"), None);
+        assert_eq!(parsed.stash_item("P2").unwrap().help_index, NO_HELP);
+        assert_eq!(parsed.get("P2").unwrap().variables, ["Damage", "Target"]);
+        assert_eq!(parsed.get("P3").unwrap().variables, ["Target"]);
+        // Each distinct string is only written once
+        assert_eq!(parsed.messages().len(), 3);
+        assert_eq!(parsed.variables().len(), 2);
+
+        assert_eq!(written(&parsed), written(&store));
+    }
+
+    #[test]
+    fn strings_with_nuls_are_rejected() {
+        let mut store = MessageStore::new();
+        store.insert("P1", "bad\0text", None, &[]);
+        assert!(matches!(
+            store.write(&mut vec![]),
+            Err(Error::EncodeError(_))
+        ));
+    }
+
+    fn tooltips() -> MessageStore {
+        MessageStore::parse_text(
+            r#"
+"P100", "Grants {Magnitude} points of protection"
+"P101", "{Count} targets in a {Radius} foot radius"
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn format_substitutes_defined_variables() {
+        let store = tooltips();
+        assert_eq!(store.get("P100").unwrap().variables, ["Magnitude"]);
+        assert_eq!(
+            store.format("P100", &[("Magnitude", 7.5.into())]).unwrap(),
+            "Grants 7.5 points of protection"
+        );
+        assert_eq!(
+            store
+                .format("P101", &[("Radius", 15.into()), ("Count", 1.into())])
+                .unwrap(),
+            "1 targets in a 15 foot radius"
+        );
+    }
+
+    #[test]
+    fn format_writes_f32_arguments_without_widening_noise() {
+        let mut store = MessageStore::new();
+        store.insert("P1", "Grants {Magnitude} protection", None, &["Magnitude"]);
+        assert_eq!(
+            store.format("P1", &[("Magnitude", 0.3f32.into())]).unwrap(),
+            "Grants 0.3 protection"
+        );
+        assert_eq!(
+            store.format("P1", &[("Magnitude", 2.5f32.into())]).unwrap(),
+            "Grants 2.5 protection"
+        );
+    }
+
+    #[test]
+    fn format_follows_typed_definitions() {
+        let mut store = MessageStore::new();
+        store.insert(
+            "P1",
+            "{Count} hits on {Target} for {Damage}",
+            None,
+            &["Count int", "Target string", "Damage float"],
+        );
+        assert_eq!(
+            store
+                .format(
+                    "P1",
+                    &[
+                        ("Count", 2.6f32.into()),
+                        ("Target", "Hero".into()),
+                        ("Damage", 12.into()),
+                    ]
+                )
+                .unwrap(),
+            "3 hits on Hero for 12"
+        );
+        assert!(matches!(
+            store.format(
+                "P1",
+                &[
+                    ("Count", "many".into()),
+                    ("Target", "Hero".into()),
+                    ("Damage", 12.into()),
+                ]
+            ),
+            Err(Error::EncodeError(_))
+        ));
+    }
+
+    #[test]
+    fn format_checks_arguments_against_definitions() {
+        let store = tooltips();
+        assert!(matches!(
+            store.format("P100", &[]),
+            Err(Error::ItemNotFound(_))
+        ));
+        assert!(matches!(
+            store.format("P100", &[("Magnitude", 1.into()), ("Extra", 2.into())]),
+            Err(Error::ItemNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn format_leaves_undefined_braces_alone() {
+        let mut store = MessageStore::new();
+        store.insert("P1", "{Target} takes {Damage}", None, &["Target string"]);
+        assert_eq!(
+            store.format("P1", &[("Target", "Hero".into())]).unwrap(),
+            "Hero takes {Damage}"
+        );
+    }
+
+    #[test]
+    fn lenient_parsing_returns_warnings() {
+        let mut store = MessageStore::new();
+        store.insert("P1", "kept", None, &[]);
+        store.insert("P2", "also kept", None, &[]);
+        let mut bytes = written(&store);
+        bytes[..4].copy_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(b"junk");
+
+        assert!(matches!(
+            MessageStore::parse(&bytes),
+            Err(Error::MessageStoreError {
+                version: 1,
+                offset: 0,
+                ..
+            })
+        ));
+
+        let (parsed, warnings) = MessageStore::parse_with(&bytes, ParseMode::Lenient).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed.text("P2"), Some("also kept"));
+        assert_eq!(warnings.len(), 2);
+        assert!(matches!(
+            warnings[1],
+            Error::MessageStoreError { offset, .. } if offset == bytes.len() - 4
+        ));
+    }
+
+    fn store(messages: &[(&str, &str)]) -> MessageStore {
+        let mut store = MessageStore::new();
+        for &(id, text) in messages {
+            store.insert(id, text, None, &[]);
+        }
+        store
+    }
+
+    fn localized() -> LocalizedMessages {
+        let mut messages = LocalizedMessages::new("en");
+        messages.insert("en", store(&[("P1", "Fire"), ("P2", "Ice")]));
+        messages.insert("fr", store(&[("P1", "Feu"), ("P3", "Extra")]));
+        messages
+    }
+
+    #[test]
+    fn locales_fall_back_to_the_default() {
+        let messages = localized();
+        assert_eq!(messages.text("fr", "P1"), Some("Feu"));
+        // Missing from fr
+        assert_eq!(messages.text("fr", "P2"), Some("Ice"));
+        // Only in fr
+        assert_eq!(messages.text("fr", "P3"), Some("Extra"));
+        assert_eq!(messages.text("en", "P3"), None);
+        // An unknown locale is all fallback
+        assert_eq!(messages.text("de", "P1"), Some("Fire"));
+        assert_eq!(messages.locale("fr").lookup("P2"), Some("Ice"));
+        assert!(matches!(
+            messages.format("fr", "P4", &[]),
+            Err(Error::ItemNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn report_lists_missing_and_extra_ids() {
+        assert_eq!(
+            localized().report(),
+            [LocaleReport {
+                locale: "fr",
+                missing: vec!["P2"],
+                extra: vec!["P3"],
+            }]
+        );
+    }
+
+    #[test]
+    fn missing_default_locale_has_no_fallback() {
+        let mut messages = LocalizedMessages::new("en");
+        messages.insert("fr", store(&[("P1", "Feu")]));
+        assert_eq!(messages.text("fr", "P1"), Some("Feu"));
+        assert_eq!(messages.text("fr", "P2"), None);
+        assert_eq!(messages.text("en", "P1"), None);
+        assert_eq!(
+            messages.report(),
+            [LocaleReport {
+                locale: "fr",
+                missing: vec![],
+                extra: vec!["P1"],
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_text_reads_the_documented_forms() {
+        let store = MessageStore::parse_text(
+            "\u{feff}\"P12345\" \"Display text\"\n\
+             \n\
+             # A comment\n\
+             // Another comment\n\
+             \"P2\"\t\"Text\" \"Help text\" # Trailing comment\n\
+             \"P3\", \"Commas\", \"Also work\" // Trailing comment\n",
+        )
+        .unwrap();
+        assert_eq!(store.len(), 3);
+        // The BOM isn't part of the first ID
+        assert_eq!(store.text("P12345"), Some("Display text"));
+        assert_eq!(store.help("P12345"), None);
+        assert_eq!(store.text("P2"), Some("Text"));
+        assert_eq!(store.help("P2"), Some("Help text"));
+        assert_eq!(store.help("P3"), Some("Also work"));
+    }
+
+    #[test]
+    fn parse_text_unescapes_strings() {
+        let store =
+            MessageStore::parse_text(r#""P1" "Say \"hi\"\nthen\ttab \\ and # // inside""#).unwrap();
+        assert_eq!(
+            store.text("P1"),
+            Some("Say \"hi\"\nthen\ttab \\ and # // inside")
+        );
+    }
+
+    #[test]
+    fn parse_text_replaces_duplicate_ids() {
+        let store =
+            MessageStore::parse_text("\"P1\" \"first\" \"help\"\n\"P1\" \"second\"\n").unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.text("P1"), Some("second"));
+        assert_eq!(store.help("P1"), None);
+    }
+
+    #[test]
+    fn parse_text_reports_the_bad_line() {
+        for (input, line) in [
+            ("\"P1\" \"ok\"\n\"P2\" \"bad \\q\"", "line 2"),
+            ("\"P1\" \"unterminated", "line 1"),
+            ("\"P1\"", "line 1"),
+            ("\"P1\" \"a\" \"b\" \"c\"", "line 1"),
+            ("\n\nP1 \"bare\"", "line 3"),
+        ] {
+            match MessageStore::parse_text(input) {
+                Err(Error::ParseError(msg)) => assert!(msg.starts_with(line), "{}", msg),
+                other => panic!("{:?} parsed as {:?}", input, other.map(|s| s.len())),
+            }
+        }
+    }
+}
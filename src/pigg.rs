@@ -1,74 +1,664 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::AsRef;
+use std::fmt;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
 use std::path::Path;
 use std::str;
+use std::sync::Mutex;
 
-use libflate::zlib;
-use memmap::MmapOptions;
+use flate2::read::ZlibDecoder;
+use memmap::{Mmap, MmapOptions};
 use nom::{bytes::complete::*, number::complete::*};
 
+use crate::common::decode_cp1252;
 use crate::error::{Error, Result};
+use crate::pigg_files::{FileIter, PiggFiles};
+use crate::pigg_update;
+
+/// Marks the start of a pigg file
+pub(crate) const ARCHIVE_MAGIC: u32 = 0x123;
+/// Marks the start of each file header
+pub(crate) const FILE_HEADER_MAGIC: u32 = 0x3456;
+/// Marks the start of the file name pool
+pub(crate) const STRING_POOL_MAGIC: u32 = 0x6789;
+/// Marks the start of the header data pool
+pub(crate) const HEADER_DATA_POOL_MAGIC: u32 = 0x9abc;
+/// `header_data_id` for files without any header data
+pub(crate) const NO_HEADER_DATA: u32 = u32::MAX;
+
+/// Newest `required_read_version` this library can read
+pub(crate) const READ_VERSION: u16 = 2;
+/// Size of the archive header this library understands, including its magic
+pub(crate) const ARCHIVE_HEADER_LEN: usize = 16;
+/// Size of each file header this library understands, including its magic
+pub(crate) const FILE_HEADER_LEN: usize = 48;
+/// Size of a pool's magic, entry count, and pool size
+const POOL_HEADER_LEN: usize = 12;
+/// Most that's reserved for decompressing a file before seeing its data
+const MAX_PREALLOCATION: usize = 64 << 20;
 
 /// PIGG extractor
-#[derive(Debug)]
-pub struct Pigg {
+///
+/// The pigg's bytes are read from `S`, which by default is a memory mapped
+/// file. See `Storage` for the other options.
+pub struct Pigg<S = Mmap> {
+    header: ArchiveHeader,
     files: HashMap<String, FileHeader>,
-    mmap: memmap::Mmap,
+    header_data: Vec<Vec<u8>>,
+    storage: S,
 }
 
 impl Pigg {
     /// Opens and parses the given pigg file
+    ///
+    /// The file is memory mapped rather than read up front. If another
+    /// process truncates or rewrites the file while it's open, reads may
+    /// return garbage or the process may be killed by the OS (e.g. `SIGBUS`).
+    /// Use `Pigg::from_reader` if that's a possibility.
+    ///
+    /// Fails with `Error::InterruptedUpdate` if a `PiggUpdater` commit to the
+    /// file was interrupted, as its headers may be torn.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Pigg> {
+        let path = path.as_ref();
+        if pigg_update::has_pending_journal(path)? {
+            return Err(Error::InterruptedUpdate(path.to_path_buf()));
+        }
         let f = File::open(path)?;
-        // TODO: Document this
+        // Safety: Mapping a file is only unsafe because the file may be
+        // modified out from under us, which is documented above
         let mmap = unsafe { MmapOptions::new().map(&f)? };
-        let (mut remaining, header) = parse_header(&mmap[..])?;
+        Pigg::with_storage(mmap)
+    }
+}
+
+impl<'a> Pigg<&'a [u8]> {
+    /// Parses a pigg held in memory, borrowing from it
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Pigg<&'a [u8]>> {
+        Pigg::with_storage(bytes)
+    }
+}
 
+impl Pigg<Vec<u8>> {
+    /// Parses a pigg held in memory, taking ownership of it
+    pub fn from_vec(bytes: Vec<u8>) -> Result<Pigg<Vec<u8>>> {
+        Pigg::with_storage(bytes)
+    }
+}
+
+impl<R: Read + Seek> Pigg<ReaderStorage<R>> {
+    /// Parses a pigg from any seekable reader
+    ///
+    /// Only the headers are read up front; file data is read on demand, so
+    /// the reader must remain valid for as long as the `Pigg` is used.
+    pub fn from_reader(reader: R) -> Result<Pigg<ReaderStorage<R>>> {
+        Pigg::with_storage(ReaderStorage::new(reader)?)
+    }
+}
+
+impl<S: Storage> Pigg<S> {
+    /// Parses a pigg out of the given storage
+    pub fn with_storage(storage: S) -> Result<Pigg<S>> {
+        let (_, header) = parse_header(&storage.read_range(0..ARCHIVE_HEADER_LEN)?)?;
+        header.validate()?;
+        let mut pos = header.archive_header_size as usize;
+
+        // Anything past the fields we know about in each header is skipped
+        let stride = header.file_header_size as usize;
+        let file_headers_len = stride
+            .checked_mul(header.num_files as usize)
+            .ok_or_else(|| Error::ParseError("file header table too large".into()))?;
+        let file_header_bytes = storage.read_range(pos..pos + file_headers_len)?;
         let mut file_headers = vec![];
-        for _ in 0..header.num_files {
-            let (new_remaining, file_header) = parse_file_header(&remaining)?;
-            remaining = new_remaining;
+        for (index, chunk) in file_header_bytes.chunks_exact(stride).enumerate() {
+            let (_, mut file_header) = parse_file_header(chunk)?;
+            file_header.index = index;
             file_headers.push(file_header);
         }
+        pos += file_headers_len;
 
-        let (_, string_pool) = StringPool::with(remaining)?;
+        let (pool, new_pos) = read_pool(&storage, pos)?;
+        let (_, string_pool) = StringPool::with(&pool)?;
+        pos = new_pos;
+
+        // Older piggs end after the name pool, possibly with some padding
+        let has_header_data = pos + 4 <= storage.size()
+            && storage.read_range(pos..pos + 4)?[..] == HEADER_DATA_POOL_MAGIC.to_le_bytes();
+        let header_data = if has_header_data {
+            let (pool, _) = read_pool(&storage, pos)?;
+            HeaderDataPool::with(&pool)?.1
+        } else {
+            HeaderDataPool::default()
+        };
 
         let files: HashMap<_, _> = string_pool
             .strings
             .into_iter()
-            .zip(file_headers.into_iter())
+            .zip(file_headers)
+            .map(|((path, raw_path), mut file_header)| {
+                file_header.raw_path = raw_path;
+                (path, file_header)
+            })
             .collect();
 
-        Ok(Pigg { files, mmap })
+        Ok(Pigg {
+            header,
+            files,
+            header_data: header_data.entries,
+            storage,
+        })
+    }
+
+    /// The pigg's archive header, including the format versions it was
+    /// written with
+    pub fn header(&self) -> &ArchiveHeader {
+        &self.header
+    }
+
+    /// The storage the pigg is read from
+    pub fn storage(&self) -> &S {
+        &self.storage
     }
 
     /// Gets the data chunk for the given path from the pigg file
     pub fn get_data(&self, path: &str) -> Result<Vec<u8>> {
-        // TODO: Would this be better served as returning a Cow instead?
-        // Return a Cow::Borrowed in the case we can just point at the map,
-        // and return a Cow::Owned if we need to decompress
+        self.get_data_cow(path).map(Cow::into_owned)
+    }
+
+    /// Gets the data chunk for the given path from the pigg file, without
+    /// copying it if possible
+    ///
+    /// Files stored uncompressed are borrowed straight out of the pigg, and
+    /// only compressed files are decompressed into a new buffer.
+    pub fn get_data_cow(&self, path: &str) -> Result<Cow<'_, [u8]>> {
         let header = self
             .files
             .get(path)
             .ok_or_else(|| Error::ItemNotFound(path.into()))?;
 
-        // TODO: Only decompress if if header.size bigger than header.pack_size
-        let begin = header.offset as usize;
-        let end = begin + header.pack_size as usize;
-        let mut data = Vec::new();
-        let mut decoder = zlib::Decoder::new(&self.mmap[begin..end])?;
+        let chunk = self.storage.read_range(header.data_range())?;
+
+        if header.is_stored() {
+            return Ok(chunk);
+        }
+
+        // The size is untrusted until `verify` checks it, so don't allocate
+        // all of a corrupted one up front
+        let mut data = Vec::with_capacity((header.size as usize).min(MAX_PREALLOCATION));
+        let mut decoder = ZlibDecoder::new(&chunk[..]);
         decoder.read_to_end(&mut data)?;
 
-        Ok(data)
+        Ok(Cow::Owned(data))
+    }
+
+    /// Gets everything needed to copy the given path into another pigg,
+    /// except for its data
+    ///
+    /// See `get_raw_data`
+    pub(crate) fn get_raw_file(&self, path: &str) -> Result<RawFile<'_>> {
+        let header = self
+            .files
+            .get(path)
+            .ok_or_else(|| Error::ItemNotFound(path.into()))?;
+
+        Ok(RawFile {
+            size: header.size,
+            timestamp: header.timestamp,
+            checksum: header.checksum,
+            stored: header.is_stored(),
+            len: header.data_range().len(),
+            header_data: self.get_header_data(path)?,
+        })
+    }
+
+    /// Gets the given path's data exactly as stored, without decompressing it
+    pub(crate) fn get_raw_data(&self, path: &str) -> Result<Cow<'_, [u8]>> {
+        let header = self
+            .files
+            .get(path)
+            .ok_or_else(|| Error::ItemNotFound(path.into()))?;
+        self.storage.read_range(header.data_range())
+    }
+
+    /// Gets the position of the `offset` field in the given path's file
+    /// header, so it can be patched in place
+    pub(crate) fn offset_field_position(&self, path: &str) -> Result<usize> {
+        let header = self
+            .files
+            .get(path)
+            .ok_or_else(|| Error::ItemNotFound(path.into()))?;
+        // The magic, name_id, size, and timestamp come first
+        Ok(self.header.archive_header_size as usize
+            + self.header.file_header_size as usize * header.index
+            + 16)
+    }
+
+    /// Checks the given path's data against its recorded size and checksum
+    ///
+    /// Returns every problem found, which is empty if the file is intact.
+    /// Overlaps with other files are only detected by `verify_all`.
+    pub fn verify(&self, path: &str) -> Result<Vec<Corruption>> {
+        let header = self
+            .files
+            .get(path)
+            .ok_or_else(|| Error::ItemNotFound(path.into()))?;
+        Ok(self
+            .verify_header_data(path, header)
+            .into_iter()
+            .chain(self.verify_file(path, header))
+            .collect())
+    }
+
+    /// Checks every file in the pigg against its recorded size and checksum,
+    /// and checks that no two files' data overlaps
+    ///
+    /// Returns every problem found, which is empty if the pigg is intact
+    pub fn verify_all(&self) -> Vec<Corruption> {
+        let mut problems: Vec<_> = self
+            .files
+            .iter()
+            .flat_map(|(path, header)| {
+                self.verify_header_data(path, header)
+                    .into_iter()
+                    .chain(self.verify_file(path, header))
+            })
+            .collect();
+
+        let mut ranges: Vec<_> = self
+            .files
+            .iter()
+            .map(|(path, header)| (header.data_range(), path))
+            .filter(|(range, _)| !range.is_empty())
+            .collect();
+        ranges.sort_by_key(|(range, path)| (range.start, range.end, path.as_str()));
+
+        // Compare against whichever earlier range reaches the furthest, so an
+        // overlap isn't hidden by a short range sitting between the two
+        let mut furthest: Option<&(Range<usize>, &String)> = None;
+        for current in &ranges {
+            if let Some(previous) = furthest {
+                // Files sharing identical data is deduplication, not corruption
+                if current.0.start < previous.0.end && current.0 != previous.0 {
+                    problems.push(Corruption::Overlapping {
+                        path: current.1.clone(),
+                        other: previous.1.clone(),
+                    });
+                }
+                if current.0.end <= previous.0.end {
+                    continue;
+                }
+            }
+            furthest = Some(current);
+        }
+
+        problems
+    }
+
+    fn verify_header_data(&self, path: &str, header: &FileHeader) -> Option<Corruption> {
+        let id = header.header_data_id;
+        if id == NO_HEADER_DATA || (id as usize) < self.header_data.len() {
+            return None;
+        }
+        Some(Corruption::MissingHeaderData {
+            path: path.into(),
+            id,
+            pool_len: self.header_data.len(),
+        })
+    }
+
+    fn verify_file(&self, path: &str, header: &FileHeader) -> Option<Corruption> {
+        let range = header.data_range();
+        if range.end > self.storage.size() {
+            return Some(Corruption::Truncated {
+                path: path.into(),
+                end: range.end,
+                pigg_len: self.storage.size(),
+            });
+        }
+
+        let data = match self.get_data_cow(path) {
+            Ok(data) => data,
+            Err(err) => {
+                return Some(Corruption::Undecodable {
+                    path: path.into(),
+                    reason: err.to_string(),
+                })
+            }
+        };
+
+        if data.len() != header.size as usize {
+            return Some(Corruption::SizeMismatch {
+                path: path.into(),
+                expected: header.size,
+                actual: data.len(),
+            });
+        }
+
+        let actual = u128::from_le_bytes(md5::compute(&data).0);
+        if actual != header.checksum {
+            return Some(Corruption::ChecksumMismatch {
+                path: path.into(),
+                expected: header.checksum,
+                actual,
+            });
+        }
+
+        None
+    }
+
+    /// Gets the header data for the given path from the pigg file, if it has
+    /// any
+    ///
+    /// This holds a small format-specific header, such as a texture's header,
+    /// so it can be inspected without decompressing the whole file. A file
+    /// referring to header data the pigg doesn't hold is an error, though
+    /// the rest of the pigg can still be read.
+    pub fn get_header_data(&self, path: &str) -> Result<Option<&[u8]>> {
+        let header = self
+            .files
+            .get(path)
+            .ok_or_else(|| Error::ItemNotFound(path.into()))?;
+
+        if let Some(problem) = self.verify_header_data(path, header) {
+            return Err(Error::ParseError(problem.to_string()));
+        }
+        Ok(match header.header_data_id {
+            NO_HEADER_DATA => None,
+            id => Some(&self.header_data[id as usize][..]),
+        })
+    }
+
+    /// Gets the path exactly as stored in the pigg's name pool
+    ///
+    /// Paths are stored in Windows-1252 and decoded when the pigg is parsed,
+    /// so this only differs from `path` for non-ASCII names.
+    pub fn raw_path(&self, path: &str) -> Result<&[u8]> {
+        self.files
+            .get(path)
+            .map(|header| &header.raw_path[..])
+            .ok_or_else(|| Error::ItemNotFound(path.into()))
+    }
+
+    /// Finds the decoded path of the file stored under the given raw name,
+    /// for use with the other lookup methods
+    ///
+    /// Useful when the name comes from other game data, which may not be
+    /// valid UTF-8 either.
+    pub fn path_for_raw(&self, raw_path: &[u8]) -> Option<&str> {
+        let (path, header) = self.files.get_key_value(&decode_cp1252(raw_path))?;
+        if header.raw_path == raw_path {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the metadata for the given path from the pigg file
+    pub fn metadata(&self, path: &str) -> Result<FileMetadata> {
+        self.files
+            .get(path)
+            .map(FileMetadata::from)
+            .ok_or_else(|| Error::ItemNotFound(path.into()))
+    }
+
+    /// Iterates over every file in the pigg, yielding its path and metadata
+    ///
+    /// No particular order is guaranteed
+    pub fn files(&self) -> impl Iterator<Item = (&str, FileMetadata)> {
+        self.files
+            .iter()
+            .map(|(path, header)| (path.as_str(), FileMetadata::from(header)))
+    }
+
+    /// Number of files stored in the pigg
+    pub fn len(&self) -> usize {
+        self.files.len()
     }
 
-    // TODO: Add a method to list the "files"
+    /// Whether the pigg contains no files
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Whether the pigg contains the given path
+    pub fn contains(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
 }
 
-fn parse_header(input: &[u8]) -> Result<(&[u8], Header)> {
-    let (input, _) = tag(&0x123u32.to_le_bytes())(input)?;
+impl<S: Storage> PiggFiles for Pigg<S> {
+    fn files(&self) -> FileIter<'_> {
+        Box::new(Pigg::files(self))
+    }
+
+    fn get_data_cow(&self, path: &str) -> Result<Cow<'_, [u8]>> {
+        Pigg::get_data_cow(self, path)
+    }
+}
+
+// Written by hand to avoid dumping in-memory piggs byte by byte
+impl<S> fmt::Debug for Pigg<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pigg")
+            .field("header", &self.header)
+            .field("files", &self.files)
+            .field("header_data", &self.header_data.len())
+            .finish()
+    }
+}
+
+/// Somewhere the bytes of a pigg can be read from
+///
+/// Implemented for memory mapped files, byte slices, byte vectors, and
+/// `ReaderStorage` (which wraps any `Read + Seek`).
+pub trait Storage {
+    /// Total size of the pigg in bytes
+    fn size(&self) -> usize;
+
+    /// Reads the given range of bytes, borrowing them if possible
+    ///
+    /// Ranges extending past the end of the pigg are an error.
+    fn read_range(&self, range: Range<usize>) -> Result<Cow<'_, [u8]>>;
+}
+
+impl Storage for Mmap {
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn read_range(&self, range: Range<usize>) -> Result<Cow<'_, [u8]>> {
+        read_slice(self, range)
+    }
+}
+
+impl Storage for Vec<u8> {
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn read_range(&self, range: Range<usize>) -> Result<Cow<'_, [u8]>> {
+        read_slice(self, range)
+    }
+}
+
+impl Storage for &[u8] {
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn read_range(&self, range: Range<usize>) -> Result<Cow<'_, [u8]>> {
+        read_slice(self, range)
+    }
+}
+
+/// Reads a pigg from a `Read + Seek` on demand
+///
+/// Every read copies, and reads are serialized through a lock.
+#[derive(Debug)]
+pub struct ReaderStorage<R> {
+    reader: Mutex<R>,
+    size: usize,
+}
+
+impl<R: Read + Seek> ReaderStorage<R> {
+    /// Wraps the given reader
+    pub fn new(mut reader: R) -> Result<ReaderStorage<R>> {
+        let size = reader.seek(SeekFrom::End(0))? as usize;
+        Ok(ReaderStorage {
+            reader: Mutex::new(reader),
+            size,
+        })
+    }
+
+    /// Unwraps the reader
+    pub fn into_inner(self) -> R {
+        self.reader
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<R: Read + Seek> Storage for ReaderStorage<R> {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read_range(&self, range: Range<usize>) -> Result<Cow<'_, [u8]>> {
+        if range.start > range.end || range.end > self.size {
+            return Err(out_of_bounds(&range, self.size));
+        }
+
+        // A panic mid-read leaves the position unknown, but every read seeks
+        // first anyway
+        let mut reader = self
+            .reader
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        reader.seek(SeekFrom::Start(range.start as u64))?;
+        let mut data = vec![0; range.len()];
+        reader.read_exact(&mut data)?;
+        Ok(Cow::Owned(data))
+    }
+}
+
+fn read_slice(bytes: &[u8], range: Range<usize>) -> Result<Cow<'_, [u8]>> {
+    bytes
+        .get(range.clone())
+        .map(Cow::Borrowed)
+        .ok_or_else(|| out_of_bounds(&range, bytes.len()))
+}
+
+fn out_of_bounds(range: &Range<usize>, size: usize) -> Error {
+    Error::ParseError(format!(
+        "{}..{} lies outside of the {} byte pigg",
+        range.start, range.end, size
+    ))
+}
+
+/// A file's headers as stored in a pigg
+pub(crate) struct RawFile<'a> {
+    pub size: u32,
+    pub timestamp: u32,
+    pub checksum: u128,
+    /// Whether the data is uncompressed
+    pub stored: bool,
+    /// Length of the data as stored
+    pub len: usize,
+    pub header_data: Option<&'a [u8]>,
+}
+
+/// Publicly visible information about a file stored in a pigg
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// Size of the file once decompressed
+    pub size: u32,
+    /// Size of the file as stored in the pigg
+    pub pack_size: u32,
+    /// Modification time, in seconds since the unix epoch
+    pub timestamp: u32,
+    /// Offset of the file's data from the start of the pigg
+    pub offset: u32,
+    /// MD5 digest of the decompressed file
+    pub checksum: u128,
+}
+
+/// A problem found while verifying a pigg
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    /// The file's data extends past the end of the pigg
+    #[error("{path} data ends at {end}, but the pigg is only {pigg_len} bytes")]
+    Truncated {
+        /// Path of the damaged file
+        path: String,
+        /// Offset the file's data should end at
+        end: usize,
+        /// Actual size of the pigg
+        pigg_len: usize,
+    },
+    /// The file's data couldn't be decompressed
+    #[error("{path} couldn't be decompressed: {reason}")]
+    Undecodable {
+        /// Path of the damaged file
+        path: String,
+        /// Why decompression failed
+        reason: String,
+    },
+    /// The file's data decompressed to the wrong size
+    #[error("{path} should be {expected} bytes, but is {actual} bytes")]
+    SizeMismatch {
+        /// Path of the damaged file
+        path: String,
+        /// Size recorded in the file header
+        expected: u32,
+        /// Size after decompressing
+        actual: usize,
+    },
+    /// The file's data doesn't match its recorded checksum
+    #[error("{path} should have checksum {expected:032x}, but has {actual:032x}")]
+    ChecksumMismatch {
+        /// Path of the damaged file
+        path: String,
+        /// Checksum recorded in the file header
+        expected: u128,
+        /// MD5 digest of the decompressed data
+        actual: u128,
+    },
+    /// The file's header refers to header data the pigg doesn't hold
+    #[error("{path} refers to header data {id}, but the pigg only holds {pool_len}")]
+    MissingHeaderData {
+        /// Path of the damaged file
+        path: String,
+        /// `header_data_id` recorded in the file header
+        id: u32,
+        /// Number of entries in the header data pool
+        pool_len: usize,
+    },
+    /// The file's data overlaps another file's data
+    #[error("{path} data overlaps {other} data")]
+    Overlapping {
+        /// Path of the file starting later in the pigg
+        path: String,
+        /// Path of the file it overlaps
+        other: String,
+    },
+}
+
+impl From<&FileHeader> for FileMetadata {
+    fn from(header: &FileHeader) -> Self {
+        FileMetadata {
+            size: header.size,
+            pack_size: header.pack_size,
+            timestamp: header.timestamp,
+            offset: header.offset,
+            checksum: header.checksum,
+        }
+    }
+}
+
+fn parse_header(input: &[u8]) -> Result<(&[u8], ArchiveHeader)> {
+    let (input, _) = tag(&ARCHIVE_MAGIC.to_le_bytes())(input)?;
 
     let (input, creator_version) = le_u16(input)?;
     let (input, required_read_version) = le_u16(input)?;
@@ -78,7 +668,7 @@ fn parse_header(input: &[u8]) -> Result<(&[u8], Header)> {
 
     Ok((
         input,
-        Header {
+        ArchiveHeader {
             creator_version,
             required_read_version,
             archive_header_size,
@@ -88,17 +678,51 @@ fn parse_header(input: &[u8]) -> Result<(&[u8], Header)> {
     ))
 }
 
-#[derive(Debug)]
-struct Header {
-    creator_version: u16,
-    required_read_version: u16,
-    archive_header_size: u16,
-    file_header_size: u16,
-    num_files: u32,
+/// The fixed header at the start of every pigg
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArchiveHeader {
+    /// Version of the tool that wrote the pigg
+    pub creator_version: u16,
+    /// Oldest reader version able to understand the pigg
+    pub required_read_version: u16,
+    /// Size of this header, including its magic
+    pub archive_header_size: u16,
+    /// Size of each file header, including its magic
+    pub file_header_size: u16,
+    /// Number of files in the pigg
+    pub num_files: u32,
+}
+
+impl ArchiveHeader {
+    /// Checks that the rest of the pigg can be read
+    ///
+    /// Headers larger than the ones we know about are fine, as the extra
+    /// bytes are skipped, but smaller ones are missing fields.
+    fn validate(&self) -> Result<()> {
+        if self.required_read_version > READ_VERSION {
+            return Err(Error::UnsupportedVersion {
+                format: "pigg",
+                version: self.required_read_version.into(),
+            });
+        }
+        if (self.archive_header_size as usize) < ARCHIVE_HEADER_LEN {
+            return Err(Error::ParseError(format!(
+                "archive header size {} is smaller than {}",
+                self.archive_header_size, ARCHIVE_HEADER_LEN
+            )));
+        }
+        if (self.file_header_size as usize) < FILE_HEADER_LEN {
+            return Err(Error::ParseError(format!(
+                "file header size {} is smaller than {}",
+                self.file_header_size, FILE_HEADER_LEN
+            )));
+        }
+        Ok(())
+    }
 }
 
 fn parse_file_header(input: &[u8]) -> Result<(&[u8], FileHeader)> {
-    let (input, _) = tag(&0x3456u32.to_le_bytes())(input)?;
+    let (input, _) = tag(&FILE_HEADER_MAGIC.to_le_bytes())(input)?;
     let (input, name_id) = le_i32(input)?;
     let (input, size) = le_u32(input)?;
     let (input, timestamp) = le_u32(input)?;
@@ -119,10 +743,13 @@ fn parse_file_header(input: &[u8]) -> Result<(&[u8], FileHeader)> {
             header_data_id,
             checksum,
             pack_size,
+            index: 0,
+            raw_path: vec![],
         },
     ))
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 struct FileHeader {
     name_id: i32,
@@ -133,42 +760,288 @@ struct FileHeader {
     header_data_id: u32,
     checksum: u128,
     pack_size: u32,
+    /// Position within the file header table
+    index: usize,
+    /// Path as stored in the name pool, before decoding
+    raw_path: Vec<u8>,
+}
+
+impl FileHeader {
+    /// Where the file's data is located in the pigg
+    fn data_range(&self) -> Range<usize> {
+        let begin = self.offset as usize;
+        let len = if self.is_stored() {
+            self.size
+        } else {
+            self.pack_size
+        };
+        begin..begin + len as usize
+    }
+
+    /// Whether the file's data was stored without compression
+    ///
+    /// A `pack_size` of zero marks stored data, though some tools instead
+    /// write the uncompressed size in both fields.
+    fn is_stored(&self) -> bool {
+        self.pack_size == 0 || self.pack_size == self.size
+    }
 }
 
 #[derive(Debug)]
 struct StringPool {
-    strings: Vec<String>,
+    /// Each decoded path along with its raw bytes
+    strings: Vec<(String, Vec<u8>)>,
 }
 
 impl StringPool {
     fn with(input: &[u8]) -> Result<(&[u8], StringPool)> {
-        let (mut input, (num_strings, _pool_size)) = Self::get_header(input)?;
-        // TODO: Actually validate pool size?
+        let (remaining, num_strings, mut input) = parse_pool(input, STRING_POOL_MAGIC)?;
 
         let mut strings = vec![];
 
         for _ in 0..num_strings {
-            let (remaining, s) = Self::read_string(input)?;
-            strings.push(s);
-            input = remaining;
+            let (new_input, raw) = Self::read_string(input)?;
+            // Paths are written in the client's code page rather than UTF-8
+            strings.push((decode_cp1252(raw), raw.to_vec()));
+            input = new_input;
         }
+        check_pool_consumed("name", input)?;
 
-        Ok((input, StringPool { strings }))
-    }
-
-    fn get_header(input: &[u8]) -> Result<(&[u8], (u32, u32))> {
-        let (input, _) = tag(&0x6789u32.to_le_bytes())(input)?;
-        let (input, num_strings) = le_u32(input)?;
-        let (input, pool_size) = le_u32(input)?;
-        Ok((input, (num_strings, pool_size)))
+        Ok((remaining, StringPool { strings }))
     }
 
-    fn read_string(input: &[u8]) -> Result<(&[u8], String)> {
+    fn read_string(input: &[u8]) -> Result<(&[u8], &[u8])> {
         let (input, str_length) = le_u32(input)?;
+        if str_length == 0 {
+            return Err(Error::ParseError(
+                "string pool entry is missing its terminator".into(),
+            ));
+        }
         let (input, str_bytes) = take(str_length - 1)(input)?;
         let (input, _) = tag(&0u8.to_le_bytes())(input)?;
 
-        // Probably should change this to from_utf8_lossy
-        Ok((input, str::from_utf8(str_bytes)?.to_string()))
+        Ok((input, str_bytes))
+    }
+}
+
+/// Holds per-file header blobs, such as texture headers, indexed by
+/// `FileHeader::header_data_id`
+#[derive(Debug, Default)]
+struct HeaderDataPool {
+    entries: Vec<Vec<u8>>,
+}
+
+impl HeaderDataPool {
+    fn with(input: &[u8]) -> Result<(&[u8], HeaderDataPool)> {
+        let (remaining, num_entries, mut input) = parse_pool(input, HEADER_DATA_POOL_MAGIC)?;
+
+        let mut entries = vec![];
+        for _ in 0..num_entries {
+            let (new_input, len) = le_u32(input)?;
+            let (new_input, entry) = take(len)(new_input)?;
+            entries.push(entry.to_vec());
+            input = new_input;
+        }
+        check_pool_consumed("header data", input)?;
+
+        Ok((remaining, HeaderDataPool { entries }))
+    }
+}
+
+/// Reads the pool starting at `pos`, including its header, returning it and
+/// the position just past it
+fn read_pool<S: Storage>(storage: &S, pos: usize) -> Result<(Cow<'_, [u8]>, usize)> {
+    let header = storage.read_range(pos..pos + POOL_HEADER_LEN)?;
+    let (_, pool_size) = le_u32(&header[8..])?;
+    let end = pos + POOL_HEADER_LEN + pool_size as usize;
+    Ok((storage.read_range(pos..end)?, end))
+}
+
+/// Parses the header shared by both pools, returning the remaining input, the
+/// number of entries, and the bytes holding them
+///
+/// The pool size covers each entry along with its length prefix.
+fn parse_pool(input: &[u8], magic: u32) -> Result<(&[u8], u32, &[u8])> {
+    let (input, _) = tag(&magic.to_le_bytes())(input)?;
+    let (input, num_entries) = le_u32(input)?;
+    let (input, pool_size) = le_u32(input)?;
+    let (input, pool) = take(pool_size)(input)?;
+    Ok((input, num_entries, pool))
+}
+
+fn check_pool_consumed(name: &str, remaining: &[u8]) -> Result<()> {
+    if remaining.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::ParseError(format!(
+            "{} pool has {} bytes beyond its last entry",
+            name,
+            remaining.len()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+    use crate::PiggWriter;
+
+    #[test]
+    fn bad_header_data_id_only_affects_its_file() {
+        let mut writer = PiggWriter::new();
+        writer.add("a.txt", b"first", 1).unwrap();
+        writer.add("b.txt", b"second", 2).unwrap();
+        writer.set_header_data("a.txt", b"header").unwrap();
+        let mut bytes = vec![];
+        writer.write(&mut bytes).unwrap();
+
+        // Point a.txt, the first file header, past the end of the pool
+        let id_pos = ARCHIVE_HEADER_LEN + 24;
+        bytes[id_pos..id_pos + 4].copy_from_slice(&5u32.to_le_bytes());
+
+        let pigg = Pigg::from_vec(bytes).unwrap();
+        assert!(pigg.get_header_data("a.txt").is_err());
+        assert_eq!(pigg.get_header_data("b.txt").unwrap(), None);
+        assert_eq!(pigg.get_data("b.txt").unwrap(), b"second");
+        assert_eq!(
+            pigg.verify_all(),
+            vec![Corruption::MissingHeaderData {
+                path: "a.txt".into(),
+                id: 5,
+                pool_len: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn corrupted_size_is_reported_rather_than_allocated() {
+        let mut writer = PiggWriter::new();
+        writer
+            .add("a.txt", &b"compressible ".repeat(20), 1)
+            .unwrap();
+        let mut bytes = vec![];
+        writer.write(&mut bytes).unwrap();
+
+        let size_pos = ARCHIVE_HEADER_LEN + 8;
+        bytes[size_pos..size_pos + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let pigg = Pigg::from_vec(bytes).unwrap();
+        assert_eq!(pigg.get_data("a.txt").unwrap(), b"compressible ".repeat(20));
+        assert!(matches!(
+            &pigg.verify_all()[..],
+            [Corruption::SizeMismatch {
+                expected: u32::MAX,
+                ..
+            }]
+        ));
+    }
+
+    /// Writes a pigg, then pads its archive header and each file header with
+    /// the given number of extra bytes, as a newer writer might
+    fn widened_pigg(extra_archive: usize, extra_file: usize) -> Vec<u8> {
+        let mut writer = PiggWriter::new();
+        writer
+            .add("a.txt", &b"compressible ".repeat(20), 1)
+            .unwrap();
+        writer.add("b.txt", b"b", 2).unwrap();
+        writer.set_header_data("b.txt", b"hdr").unwrap();
+        let mut bytes = vec![];
+        writer.write(&mut bytes).unwrap();
+
+        let num_files = 2;
+        let shift = extra_archive + extra_file * num_files;
+        let mut widened = bytes[..ARCHIVE_HEADER_LEN].to_vec();
+        widened[8..10]
+            .copy_from_slice(&((ARCHIVE_HEADER_LEN + extra_archive) as u16).to_le_bytes());
+        widened[10..12].copy_from_slice(&((FILE_HEADER_LEN + extra_file) as u16).to_le_bytes());
+        widened.resize(widened.len() + extra_archive, 0xee);
+        let headers = &bytes[ARCHIVE_HEADER_LEN..ARCHIVE_HEADER_LEN + FILE_HEADER_LEN * num_files];
+        for header in headers.chunks(FILE_HEADER_LEN) {
+            let mut header = header.to_vec();
+            let offset = u32::from_le_bytes(header[16..20].try_into().unwrap());
+            header[16..20].copy_from_slice(&(offset + shift as u32).to_le_bytes());
+            widened.extend_from_slice(&header);
+            widened.resize(widened.len() + extra_file, 0xee);
+        }
+        widened.extend_from_slice(&bytes[ARCHIVE_HEADER_LEN + FILE_HEADER_LEN * num_files..]);
+        widened
+    }
+
+    #[test]
+    fn larger_headers_are_skipped_over() {
+        let pigg = Pigg::from_vec(widened_pigg(4, 4)).unwrap();
+        assert_eq!(pigg.header().archive_header_size, 20);
+        assert_eq!(pigg.header().file_header_size, 52);
+        assert_eq!(pigg.get_data("a.txt").unwrap(), b"compressible ".repeat(20));
+        assert_eq!(pigg.get_data("b.txt").unwrap(), b"b");
+        assert_eq!(pigg.get_header_data("b.txt").unwrap(), Some(&b"hdr"[..]));
+        assert_eq!(pigg.verify_all(), vec![]);
+        // The second file header starts after the padded archive header and
+        // the whole of the first file header
+        assert_eq!(pigg.offset_field_position("b.txt").unwrap(), 20 + 52 + 16);
+    }
+
+    #[test]
+    fn smaller_headers_are_rejected() {
+        let mut bytes = widened_pigg(0, 0);
+        bytes[10..12].copy_from_slice(&44u16.to_le_bytes());
+        assert!(matches!(Pigg::from_vec(bytes), Err(Error::ParseError(_))));
+    }
+
+    #[test]
+    fn newer_read_versions_are_rejected() {
+        let mut bytes = widened_pigg(0, 0);
+        bytes[6..8].copy_from_slice(&(READ_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            Pigg::from_vec(bytes),
+            Err(Error::UnsupportedVersion { format: "pigg", version }) if version == u32::from(READ_VERSION) + 1
+        ));
+    }
+
+    #[test]
+    fn windows_1252_paths_round_trip() {
+        // é, and 0x81, which Windows-1252 leaves undefined
+        let raw = b"caf\xe9/\x81x.txt";
+        let path = decode_cp1252(raw);
+        assert_eq!(path, "caf\u{e9}/\u{81}x.txt");
+
+        let mut writer = PiggWriter::new();
+        writer.add(&path, b"data", 1).unwrap();
+        writer.add("plain.txt", b"plain", 1).unwrap();
+        let mut bytes = vec![];
+        writer.write(&mut bytes).unwrap();
+        assert!(bytes.windows(raw.len()).any(|w| w == raw));
+
+        let pigg = Pigg::from_vec(bytes).unwrap();
+        assert_eq!(pigg.raw_path(&path).unwrap(), raw);
+        assert_eq!(pigg.path_for_raw(raw), Some(path.as_str()));
+        assert_eq!(pigg.get_data(&path).unwrap(), b"data");
+        assert_eq!(pigg.raw_path("plain.txt").unwrap(), b"plain.txt");
+        // The UTF-8 encoding of the path isn't how it's stored
+        assert_eq!(pigg.path_for_raw(path.as_bytes()), None);
+        assert!(pigg.path_for_raw(b"missing.txt").is_none());
+    }
+
+    #[test]
+    fn paths_outside_windows_1252_are_rejected() {
+        let mut writer = PiggWriter::new();
+        assert!(matches!(
+            writer.add("\u{65e5}\u{672c}.txt", b"data", 1),
+            Err(Error::EncodeError(_))
+        ));
+    }
+
+    #[test]
+    fn padding_after_name_pool_is_not_a_header_data_pool() {
+        let mut bytes = vec![];
+        PiggWriter::new().write(&mut bytes).unwrap();
+        // Swap the empty header data pool for a few bytes of padding
+        bytes.truncate(bytes.len() - POOL_HEADER_LEN);
+        bytes.extend_from_slice(&[0; 3]);
+
+        let pigg = Pigg::from_vec(bytes).unwrap();
+        assert!(pigg.is_empty());
     }
 }
@@ -0,0 +1,227 @@
+//! Comparisons between two piggs, such as before and after a patch
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::ops::Range;
+
+use serde::de::DeserializeOwned;
+
+use crate::defs;
+use crate::error::Result;
+use crate::pigg::{FileMetadata, Pigg, Storage};
+
+/// A difference between two piggs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// The path only exists in the new pigg
+    Added {
+        /// Path of the file
+        path: String,
+        /// The file's metadata in the new pigg
+        new: FileMetadata,
+    },
+    /// The path only exists in the old pigg
+    Removed {
+        /// Path of the file
+        path: String,
+        /// The file's metadata in the old pigg
+        old: FileMetadata,
+    },
+    /// The file's contents differ, going by its checksum and size
+    Changed {
+        /// Path of the file
+        path: String,
+        /// The file's metadata in the old pigg
+        old: FileMetadata,
+        /// The file's metadata in the new pigg
+        new: FileMetadata,
+    },
+    /// The file's contents are the same, but its timestamp differs
+    Touched {
+        /// Path of the file
+        path: String,
+        /// The file's metadata in the old pigg
+        old: FileMetadata,
+        /// The file's metadata in the new pigg
+        new: FileMetadata,
+    },
+}
+
+impl Change {
+    /// Path of the file which differs
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added { path, .. }
+            | Change::Removed { path, .. }
+            | Change::Changed { path, .. }
+            | Change::Touched { path, .. } => path,
+        }
+    }
+}
+
+/// Compares the files held by two piggs, sorted by path
+///
+/// Files which are identical in both piggs aren't reported.
+pub fn diff<A: Storage, B: Storage>(old: &Pigg<A>, new: &Pigg<B>) -> Vec<Change> {
+    let old_files: BTreeMap<_, _> = old.files().collect();
+    let new_files: BTreeMap<_, _> = new.files().collect();
+
+    let mut changes = vec![];
+    for (&path, &old) in &old_files {
+        let path = path.to_string();
+        let change = match new_files.get(path.as_str()) {
+            None => Change::Removed { path, old },
+            Some(&new) if old.checksum != new.checksum || old.size != new.size => {
+                Change::Changed { path, old, new }
+            }
+            Some(&new) if old.timestamp != new.timestamp => Change::Touched { path, old, new },
+            Some(_) => continue,
+        };
+        changes.push(change);
+    }
+    for (&path, &new) in &new_files {
+        if !old_files.contains_key(path) {
+            changes.push(Change::Added {
+                path: path.to_string(),
+                new,
+            });
+        }
+    }
+
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    changes
+}
+
+/// Finds the byte ranges which differ between two versions of a file
+///
+/// Bytes are compared position by position, so an insertion shows up as
+/// everything after it changing. If one version is longer, its extra bytes
+/// are reported as a final range.
+pub fn diff_bytes(old: &[u8], new: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    let mut start = None;
+    for (i, (a, b)) in old.iter().zip(new).enumerate() {
+        match (a == b, start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                ranges.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let common = old.len().min(new.len());
+    let end = old.len().max(new.len());
+    match start {
+        Some(s) => ranges.push(s..end),
+        None if common != end => ranges.push(common..end),
+        None => {}
+    }
+    ranges
+}
+
+/// A record as compared by `diff_bins`: pretty-printed, or why it failed to
+/// parse
+pub type RecordText = std::result::Result<String, String>;
+
+/// A record which differs between two versions of a bin
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordChange {
+    /// Position of the record within the bin
+    pub index: usize,
+    /// The old record, if the old bin was long enough
+    pub old: Option<RecordText>,
+    /// The new record, if the new bin was long enough
+    pub new: Option<RecordText>,
+}
+
+/// Decodes two versions of a bin as `T` and reports the records which differ
+///
+/// Records are compared by position using their pretty-printed `Debug`
+/// output, as the decoded types don't implement `PartialEq`. A record which
+/// fails to parse is kept in its position as an `Err` holding the error, so
+/// it doesn't shift the records after it.
+///
+/// This is only available from the library, not the `pigg-diff` binary, as
+/// the record type has to be chosen for each bin.
+pub fn diff_bins<T: DeserializeOwned + Debug>(old: &[u8], new: &[u8]) -> Result<Vec<RecordChange>> {
+    let old = record_texts::<T>(old)?;
+    let new = record_texts::<T>(new)?;
+
+    Ok((0..old.len().max(new.len()))
+        .filter(|&i| old.get(i) != new.get(i))
+        .map(|index| RecordChange {
+            index,
+            old: old.get(index).cloned(),
+            new: new.get(index).cloned(),
+        })
+        .collect())
+}
+
+/// Decodes each record of a bin for `diff_bins`
+fn record_texts<T: DeserializeOwned + Debug>(bin: &[u8]) -> Result<Vec<RecordText>> {
+    let mut records = defs::decode_iter::<T>(bin)?;
+    let mut texts = vec![];
+    while let Some(record) = records.next_record() {
+        texts.push(match record? {
+            Ok(item) => Ok(format!("{:#?}", item)),
+            Err(err) => Err(err.to_string()),
+        });
+    }
+    Ok(texts)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    enum Kind {
+        Melee,
+        Ranged,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Rec {
+        kind: Kind,
+        n: u32,
+    }
+
+    fn bin(last: u32) -> Vec<u8> {
+        defs::encode(&[
+            Rec {
+                kind: Kind::Melee,
+                n: 0x1122_3344,
+            },
+            Rec {
+                kind: Kind::Ranged,
+                n: 2,
+            },
+            Rec {
+                kind: Kind::Melee,
+                n: last,
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn failed_records_keep_their_position() {
+        let mut old = bin(3);
+        let n = old
+            .windows(4)
+            .position(|w| w == 0x1122_3344u32.to_le_bytes())
+            .unwrap();
+        // Make the first record's variant index invalid
+        old[n - 4..n].copy_from_slice(&99u32.to_le_bytes());
+
+        let changes = diff_bins::<Rec>(&old, &bin(4)).unwrap();
+        let indices: Vec<_> = changes.iter().map(|change| change.index).collect();
+        assert_eq!(indices, [0, 2]);
+        assert!(matches!(changes[0].old, Some(Err(_))));
+        assert!(matches!(changes[0].new, Some(Ok(_))));
+    }
+}
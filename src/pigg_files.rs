@@ -0,0 +1,144 @@
+//! Operations shared by everything which holds a flat list of pigg paths
+
+use std::borrow::Cow;
+use std::convert::AsRef;
+use std::io::{Seek, Write};
+use std::path::Path;
+
+use crate::error::Result;
+use crate::export;
+use crate::extract::{self, ExtractProgress};
+use crate::listing::{self, DirEntry};
+use crate::pigg::FileMetadata;
+
+/// Iterator over paths and their metadata, as returned by `PiggFiles`
+pub type FileIter<'a> = Box<dyn Iterator<Item = (&'a str, FileMetadata)> + 'a>;
+
+/// Searching, listing, extracting, and exporting the files of a `Pigg` or
+/// `PiggSet`
+///
+/// Everything is built on `files` and `get_data_cow`, which both types also
+/// have as inherent methods.
+pub trait PiggFiles {
+    /// Iterates over every file, yielding its path and metadata
+    ///
+    /// No particular order is guaranteed
+    fn files(&self) -> FileIter<'_>;
+
+    /// Gets the data for the given path, without copying it if possible
+    fn get_data_cow(&self, path: &str) -> Result<Cow<'_, [u8]>>;
+
+    /// Iterates over every file whose path matches the given glob pattern,
+    /// such as `texture_library/**/*.texture`
+    ///
+    /// `*` and `?` don't match across `/`, while `**` matches any number of
+    /// directories. No particular order is guaranteed.
+    fn find(&self, pattern: &str) -> Result<FileIter<'_>> {
+        Ok(Box::new(listing::find(PiggFiles::files(self), pattern)?))
+    }
+
+    /// Lists the files and subdirectories directly inside the given
+    /// directory, such as `bin/`, sorted by name
+    ///
+    /// Leading and trailing slashes are ignored, so an empty path (or `/`)
+    /// lists the top level.
+    fn read_dir(&self, dir: &str) -> Result<Vec<DirEntry>> {
+        listing::read_dir(PiggFiles::files(self), dir)
+    }
+
+    /// Extracts every file accepted by `filter` into `dest`, decompressing
+    /// across a thread pool
+    ///
+    /// Directories are created as needed, and each file's modification time
+    /// is set from its metadata. `progress` is called after each file is
+    /// written, possibly from several threads at once. Returns the number of
+    /// files extracted.
+    fn extract_all<P, F, C>(&self, dest: P, filter: F, progress: C) -> Result<usize>
+    where
+        Self: Sync,
+        P: AsRef<Path>,
+        F: Fn(&str, &FileMetadata) -> bool,
+        C: Fn(ExtractProgress<'_>) + Sync,
+    {
+        let files = PiggFiles::files(self)
+            .filter(|(path, metadata)| filter(path, metadata))
+            .collect();
+        extract::extract_all(
+            files,
+            |path| PiggFiles::get_data_cow(self, path),
+            dest.as_ref(),
+            progress,
+        )
+    }
+
+    /// Streams every file into a tar archive, keeping paths and timestamps,
+    /// and adds a manifest of the original checksums
+    ///
+    /// See `MANIFEST_NAME`. Returns the writer once the archive is finished.
+    fn export_tar<W: Write>(&self, out: W) -> Result<W> {
+        export::to_tar(
+            PiggFiles::files(self).collect(),
+            |path| PiggFiles::get_data_cow(self, path),
+            out,
+        )
+    }
+
+    /// Streams every file into a zip archive, keeping paths and timestamps,
+    /// and adds a manifest of the original checksums
+    ///
+    /// See `MANIFEST_NAME`. Returns the writer once the archive is finished.
+    fn export_zip<W: Write + Seek>(&self, out: W) -> Result<W> {
+        export::to_zip(
+            PiggFiles::files(self).collect(),
+            |path| PiggFiles::get_data_cow(self, path),
+            out,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pigg, PiggSet, PiggWriter};
+
+    fn pigg(files: &[(&str, &[u8], u32)]) -> Pigg<Vec<u8>> {
+        let mut writer = PiggWriter::new();
+        for &(path, data, timestamp) in files {
+            writer.add(path, data, timestamp).unwrap();
+        }
+        let mut bytes = vec![];
+        writer.write(&mut bytes).unwrap();
+        Pigg::from_vec(bytes).unwrap()
+    }
+
+    #[test]
+    fn piggs_and_sets_share_the_same_views() {
+        let files: &[(&str, &[u8], u32)] = &[
+            ("bin/powers.bin", b"powers", 1),
+            ("bin/classes.bin", b"classes", 2),
+            ("readme.txt", b"readme", 3),
+        ];
+        let single = pigg(files);
+        let mut set = PiggSet::new();
+        set.add(pigg(&files[..1]));
+        set.add(pigg(&files[1..]));
+
+        let mut found: Vec<_> = single.find("bin/*").unwrap().map(|(p, _)| p).collect();
+        found.sort_unstable();
+        assert_eq!(found, ["bin/classes.bin", "bin/powers.bin"]);
+        assert_eq!(set.find("bin/*").unwrap().count(), 2);
+
+        // Offsets differ between the piggs, so only compare names
+        let names = |entries: Vec<DirEntry>| -> Vec<String> {
+            entries.iter().map(|e| e.name().to_string()).collect()
+        };
+        assert_eq!(
+            names(single.read_dir("/").unwrap()),
+            names(set.read_dir("").unwrap())
+        );
+        assert_eq!(
+            single.export_tar(vec![]).unwrap(),
+            set.export_tar(vec![]).unwrap()
+        );
+    }
+}
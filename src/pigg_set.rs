@@ -0,0 +1,202 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::AsRef;
+use std::fs;
+use std::path::Path;
+
+use memmap::Mmap;
+
+use crate::error::{Error, Result};
+use crate::pigg::{FileMetadata, Pigg, Storage};
+use crate::pigg_files::{FileIter, PiggFiles};
+
+/// Several piggs layered into a single view, the way the client loads them
+///
+/// When a path is held by more than one pigg, the copy with the newest
+/// timestamp wins. Ties go to whichever pigg was added last.
+#[derive(Debug)]
+pub struct PiggSet<S = Mmap> {
+    piggs: Vec<Pigg<S>>,
+    /// Maps each path to the index of the pigg it resolves to, and the
+    /// timestamp of the copy in that pigg
+    files: HashMap<String, (usize, u32)>,
+}
+
+impl<S> Default for PiggSet<S> {
+    fn default() -> Self {
+        PiggSet {
+            piggs: vec![],
+            files: HashMap::new(),
+        }
+    }
+}
+
+impl PiggSet {
+    /// Opens every `.pigg` file directly inside the given directory
+    ///
+    /// Piggs are added in file name order, so ties are resolved the same way
+    /// on every run.
+    pub fn open_dir<P: AsRef<Path>>(dir: P) -> Result<PiggSet> {
+        let mut paths = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_pigg = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("pigg"));
+            if is_pigg && path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut set = PiggSet::new();
+        for path in paths {
+            set.add(Pigg::new(path)?);
+        }
+        Ok(set)
+    }
+}
+
+impl<S: Storage> PiggSet<S> {
+    /// Creates an empty set
+    pub fn new() -> PiggSet<S> {
+        PiggSet::default()
+    }
+
+    /// Adds a pigg to the set, letting its files override any older copies
+    pub fn add(&mut self, pigg: Pigg<S>) {
+        let index = self.piggs.len();
+        for (path, metadata) in pigg.files() {
+            let newer = match self.files.get(path) {
+                Some(&(_, current)) => metadata.timestamp >= current,
+                None => true,
+            };
+            if newer {
+                self.files
+                    .insert(path.to_string(), (index, metadata.timestamp));
+            }
+        }
+        self.piggs.push(pigg);
+    }
+
+    /// The piggs making up the set, in the order they were added
+    pub fn piggs(&self) -> &[Pigg<S>] {
+        &self.piggs
+    }
+
+    /// Gets the pigg the given path resolves to
+    pub fn pigg_for(&self, path: &str) -> Result<&Pigg<S>> {
+        self.files
+            .get(path)
+            .map(|&(index, _)| &self.piggs[index])
+            .ok_or_else(|| Error::ItemNotFound(path.into()))
+    }
+
+    /// Gets the data chunk for the given path from the newest pigg holding it
+    pub fn get_data(&self, path: &str) -> Result<Vec<u8>> {
+        self.pigg_for(path)?.get_data(path)
+    }
+
+    /// Gets the data chunk for the given path from the newest pigg holding it,
+    /// without copying it if possible
+    ///
+    /// See `Pigg::get_data_cow`
+    pub fn get_data_cow(&self, path: &str) -> Result<Cow<'_, [u8]>> {
+        self.pigg_for(path)?.get_data_cow(path)
+    }
+
+    /// Gets the header data for the given path from the newest pigg holding it
+    ///
+    /// See `Pigg::get_header_data`
+    pub fn get_header_data(&self, path: &str) -> Result<Option<&[u8]>> {
+        self.pigg_for(path)?.get_header_data(path)
+    }
+
+    /// Gets the metadata for the given path from the newest pigg holding it
+    pub fn metadata(&self, path: &str) -> Result<FileMetadata> {
+        self.pigg_for(path)?.metadata(path)
+    }
+
+    /// Iterates over every path in the set, yielding the metadata of the copy
+    /// it resolves to
+    ///
+    /// No particular order is guaranteed
+    pub fn files(&self) -> impl Iterator<Item = (&str, FileMetadata)> {
+        self.files.iter().filter_map(move |(path, &(index, _))| {
+            let metadata = self.piggs[index].metadata(path).ok()?;
+            Some((path.as_str(), metadata))
+        })
+    }
+
+    /// Number of distinct paths in the set
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Whether the set contains no files
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Whether any pigg in the set contains the given path
+    pub fn contains(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+}
+
+impl<S: Storage> PiggFiles for PiggSet<S> {
+    fn files(&self) -> FileIter<'_> {
+        Box::new(PiggSet::files(self))
+    }
+
+    fn get_data_cow(&self, path: &str) -> Result<Cow<'_, [u8]>> {
+        PiggSet::get_data_cow(self, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PiggWriter;
+
+    fn pigg(files: &[(&str, &[u8], u32)]) -> Pigg<Vec<u8>> {
+        let mut writer = PiggWriter::new();
+        for &(path, data, timestamp) in files {
+            writer.add(path, data, timestamp).unwrap();
+        }
+        let mut bytes = vec![];
+        writer.write(&mut bytes).unwrap();
+        Pigg::from_vec(bytes).unwrap()
+    }
+
+    #[test]
+    fn newest_timestamp_wins_regardless_of_order() {
+        let mut set = PiggSet::new();
+        set.add(pigg(&[("a.txt", b"new", 20), ("b.txt", b"old", 10)]));
+        set.add(pigg(&[("a.txt", b"old", 10), ("b.txt", b"new", 20)]));
+
+        assert_eq!(set.get_data("a.txt").unwrap(), b"new");
+        assert_eq!(set.get_data("b.txt").unwrap(), b"new");
+        assert_eq!(set.metadata("a.txt").unwrap().timestamp, 20);
+        assert!(std::ptr::eq(
+            set.pigg_for("a.txt").unwrap(),
+            &set.piggs()[0]
+        ));
+    }
+
+    #[test]
+    fn ties_go_to_the_last_pigg_added() {
+        let mut set = PiggSet::new();
+        set.add(pigg(&[("a.txt", b"first", 10)]));
+        set.add(pigg(&[("a.txt", b"second", 10)]));
+        set.add(pigg(&[("a.txt", b"older", 5), ("c.txt", b"only", 1)]));
+
+        assert_eq!(set.get_data("a.txt").unwrap(), b"second");
+        assert_eq!(set.get_data("c.txt").unwrap(), b"only");
+        assert_eq!(set.len(), 2);
+        assert!(matches!(
+            set.get_data("missing.txt"),
+            Err(Error::ItemNotFound(_))
+        ));
+    }
+}
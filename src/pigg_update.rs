@@ -0,0 +1,360 @@
+use std::collections::BTreeMap;
+use std::convert::AsRef;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::common::to_u32;
+use crate::error::Result;
+use crate::pigg::{Pigg, ReaderStorage, Storage};
+use crate::pigg_writer::{header_block_len, write_header_block, HeaderEntry, PiggWriter};
+
+/// Adds or replaces files in an existing pigg without rewriting all of it
+///
+/// Files are staged in memory, then `commit` appends their data to the end of
+/// the pigg and rewrites the headers at the start. Files whose data sits where
+/// the enlarged headers need to go are copied to the end first. Replaced data
+/// is left behind as an unused gap, which `PiggWriter::merge` can compact.
+///
+/// Until the final header write, nothing the existing headers reference is
+/// overwritten. The final write is journaled next to the pigg (as
+/// `<name>.journal`), and is replayed by `PiggUpdater::open` or
+/// `PiggUpdater::recover` if the process dies part way through it. While that
+/// journal exists the headers may be torn, so `Pigg::new` refuses to open the
+/// pigg with `Error::InterruptedUpdate`. Readers given the pigg some other
+/// way, such as `Pigg::from_reader`, can't see the journal and need to call
+/// `PiggUpdater::recover` themselves.
+///
+/// Nothing else should have the pigg open while it's being committed to.
+#[derive(Debug)]
+pub struct PiggUpdater {
+    path: PathBuf,
+    pigg: Pigg<ReaderStorage<File>>,
+    staged: PiggWriter<'static>,
+}
+
+impl PiggUpdater {
+    /// Opens the given pigg for updating, first finishing any interrupted
+    /// commit
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<PiggUpdater> {
+        let path = path.as_ref().to_path_buf();
+        PiggUpdater::recover(&path)?;
+        let pigg = Pigg::from_reader(File::open(&path)?)?;
+        Ok(PiggUpdater {
+            path,
+            pigg,
+            staged: PiggWriter::new(),
+        })
+    }
+
+    /// Finishes an interrupted commit to the given pigg, if there was one
+    ///
+    /// Returns whether a journal was replayed. A journal which was itself only
+    /// partly written is discarded, as the pigg wasn't touched yet.
+    pub fn recover<P: AsRef<Path>>(path: P) -> Result<bool> {
+        let journal_path = journal_path(path.as_ref());
+        let journal = match fs::read(&journal_path) {
+            Ok(journal) => journal,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+
+        let block = complete_journal(&journal);
+        if let Some(block) = block {
+            let mut out = OpenOptions::new().write(true).open(path)?;
+            out.write_all(block)?;
+            out.sync_all()?;
+        }
+
+        fs::remove_file(journal_path)?;
+        Ok(block.is_some())
+    }
+
+    /// Sets how hard staged files are compressed from now on
+    ///
+    /// See `PiggWriter::set_compression_level`
+    pub fn set_compression_level(&mut self, level: u32) {
+        self.staged.set_compression_level(level);
+    }
+
+    /// Stages a file to be added, replacing any existing file at the same path
+    ///
+    /// A replaced file's header data is kept. See `PiggWriter::add`
+    pub fn add(&mut self, path: &str, data: &[u8], timestamp: u32) -> Result<()> {
+        let restaged = self.staged.files.contains_key(path);
+        self.staged.add(path, data, timestamp)?;
+        if !restaged {
+            // A bad header data ID is reported by `Pigg::verify`, and there's
+            // nothing to carry over from it
+            if let Some(header_data) = self.pigg.get_header_data(path).ok().flatten() {
+                self.staged.set_header_data(path, header_data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Attaches header data to a previously staged file
+    ///
+    /// See `PiggWriter::set_header_data`
+    pub fn set_header_data(&mut self, path: &str, header_data: &[u8]) -> Result<()> {
+        self.staged.set_header_data(path, header_data)
+    }
+
+    /// Removes any header data from a previously staged file
+    ///
+    /// See `PiggWriter::clear_header_data`
+    pub fn clear_header_data(&mut self, path: &str) -> Result<()> {
+        self.staged.clear_header_data(path)
+    }
+
+    /// Writes the staged files into the pigg
+    pub fn commit(self) -> Result<()> {
+        let (mut out, block) = self.prepare()?;
+        write_at(&mut out, 0, &block)?;
+        out.sync_all()?;
+        fs::remove_file(journal_path(&self.path))?;
+
+        Ok(())
+    }
+
+    /// Does everything `commit` does short of writing the new headers,
+    /// returning them along with the pigg opened for writing
+    ///
+    /// The headers have been journaled by the time this returns.
+    fn prepare(&self) -> Result<(File, Vec<u8>)> {
+        let pigg = &self.pigg;
+        let mut out = OpenOptions::new().write(true).open(&self.path)?;
+
+        let mut entries = BTreeMap::new();
+        for (path, metadata) in pigg.files() {
+            let entry = HeaderEntry {
+                path,
+                size: metadata.size,
+                timestamp: metadata.timestamp,
+                offset: metadata.offset as usize,
+                checksum: metadata.checksum,
+                pack_size: metadata.pack_size,
+                header_data: pigg.get_header_data(path)?,
+            };
+            entries.insert(path, entry);
+        }
+        for (path, file) in &self.staged.files {
+            let entry = HeaderEntry {
+                path,
+                size: file.size,
+                timestamp: file.timestamp,
+                offset: 0,
+                checksum: file.checksum,
+                pack_size: file.pack_size(),
+                header_data: file.header_data.as_deref(),
+            };
+            entries.insert(path, entry);
+        }
+
+        // Sorted by path, like PiggWriter
+        let mut entries: Vec<_> = entries.into_values().collect();
+        let block_len = header_block_len(&entries);
+        let mut end = pigg.storage().size().max(block_len);
+
+        // Append the staged data
+        for (path, file) in &self.staged.files {
+            write_at(&mut out, end, &file.data()?)?;
+            set_offset(&mut entries, path, end);
+            end += file.len();
+        }
+
+        // Copy anything in the way of the new headers to the end. Replaced
+        // files are copied too, as the old headers still reference them.
+        let mut relocated = vec![];
+        for (path, metadata) in pigg.files() {
+            let len = if metadata.pack_size == 0 {
+                metadata.size
+            } else {
+                metadata.pack_size
+            };
+            if len == 0 || metadata.offset as usize >= block_len {
+                continue;
+            }
+
+            write_at(&mut out, end, &pigg.get_raw_data(path)?)?;
+            relocated.push((path, end));
+            if !self.staged.files.contains_key(path) {
+                set_offset(&mut entries, path, end);
+            }
+            end += len as usize;
+        }
+        out.sync_data()?;
+
+        // Point the old headers at the copies, leaving the space free
+        for (path, offset) in relocated {
+            let offset = to_u32(offset, "pigg")?;
+            write_at(
+                &mut out,
+                pigg.offset_field_position(path)?,
+                &offset.to_le_bytes(),
+            )?;
+        }
+        out.sync_data()?;
+
+        let mut block = Vec::with_capacity(block_len + 16);
+        write_header_block(&mut block, &entries)?;
+        let checksum = md5::compute(&block).0;
+
+        let mut journal = File::create(journal_path(&self.path))?;
+        journal.write_all(&block)?;
+        journal.write_all(&checksum)?;
+        journal.sync_all()?;
+
+        Ok((out, block))
+    }
+}
+
+/// Whether the given pigg has a complete journal waiting to be replayed by
+/// `PiggUpdater::recover`
+pub(crate) fn has_pending_journal(path: &Path) -> Result<bool> {
+    match fs::read(journal_path(path)) {
+        Ok(journal) => Ok(complete_journal(&journal).is_some()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The header block held by a journal, unless it was only partly written
+fn complete_journal(journal: &[u8]) -> Option<&[u8]> {
+    if journal.len() < 16 {
+        return None;
+    }
+    let (block, checksum) = journal.split_at(journal.len() - 16);
+    if md5::compute(block).0 == checksum {
+        Some(block)
+    } else {
+        None
+    }
+}
+
+fn set_offset(entries: &mut [HeaderEntry], path: &str, offset: usize) {
+    if let Ok(index) = entries.binary_search_by(|entry| entry.path.cmp(path)) {
+        entries[index].offset = offset;
+    }
+}
+
+fn journal_path(path: &Path) -> PathBuf {
+    let mut journal_path = OsString::from(path);
+    journal_path.push(".journal");
+    journal_path.into()
+}
+
+fn write_at(out: &mut File, pos: usize, data: &[u8]) -> Result<()> {
+    out.seek(SeekFrom::Start(pos as u64))?;
+    out.write_all(data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    /// A pigg in the temp directory, removed along with its journal on drop
+    struct TempPigg(PathBuf);
+
+    impl TempPigg {
+        fn new(name: &str, writer: &PiggWriter) -> TempPigg {
+            let path = std::env::temp_dir().join(format!(
+                "index_datamanip-{}-{}.pigg",
+                std::process::id(),
+                name
+            ));
+            writer.save(&path).unwrap();
+            TempPigg(path)
+        }
+    }
+
+    impl Drop for TempPigg {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+            let _ = fs::remove_file(journal_path(&self.0));
+        }
+    }
+
+    fn original() -> PiggWriter<'static> {
+        let mut writer = PiggWriter::new();
+        // Enough files that the new headers overlap their data
+        for i in 0..4 {
+            writer
+                .add(&format!("file{}.txt", i), &b"original ".repeat(20), 1)
+                .unwrap();
+        }
+        writer.set_header_data("file0.txt", b"hdr").unwrap();
+        writer
+    }
+
+    #[test]
+    fn commit_adds_and_replaces_files() {
+        let pigg = TempPigg::new("commit", &original());
+
+        let mut updater = PiggUpdater::open(&pigg.0).unwrap();
+        updater.add("file0.txt", b"replaced", 2).unwrap();
+        updater
+            .add("a much longer path to grow the headers.txt", b"new", 2)
+            .unwrap();
+        updater.commit().unwrap();
+
+        let updated = Pigg::new(&pigg.0).unwrap();
+        assert_eq!(updated.len(), 5);
+        assert_eq!(updated.get_data("file0.txt").unwrap(), b"replaced");
+        assert_eq!(
+            updated.get_header_data("file0.txt").unwrap(),
+            Some(&b"hdr"[..])
+        );
+        assert_eq!(
+            updated.get_data("file3.txt").unwrap(),
+            b"original ".repeat(20)
+        );
+        assert_eq!(updated.verify_all(), vec![]);
+    }
+
+    #[test]
+    fn interrupted_commit_is_detected_and_recovered() {
+        let pigg = TempPigg::new("interrupted", &original());
+
+        let mut updater = PiggUpdater::open(&pigg.0).unwrap();
+        updater.add("file1.txt", b"replaced", 2).unwrap();
+        updater
+            .add("a new file with a long path.txt", b"new", 2)
+            .unwrap();
+        let (mut out, block) = updater.prepare().unwrap();
+        // Die part way through writing the headers
+        write_at(&mut out, 0, &block[..block.len() / 2]).unwrap();
+        drop(out);
+
+        assert!(matches!(
+            Pigg::new(&pigg.0),
+            Err(Error::InterruptedUpdate(path)) if path == pigg.0
+        ));
+
+        assert!(PiggUpdater::recover(&pigg.0).unwrap());
+        assert!(!PiggUpdater::recover(&pigg.0).unwrap());
+        let recovered = Pigg::new(&pigg.0).unwrap();
+        assert_eq!(recovered.get_data("file1.txt").unwrap(), b"replaced");
+        assert_eq!(
+            recovered
+                .get_data("a new file with a long path.txt")
+                .unwrap(),
+            b"new"
+        );
+        assert_eq!(recovered.verify_all(), vec![]);
+    }
+
+    #[test]
+    fn partly_written_journal_is_discarded() {
+        let pigg = TempPigg::new("partial", &original());
+        fs::write(journal_path(&pigg.0), b"truncated").unwrap();
+
+        let untouched = Pigg::new(&pigg.0).unwrap();
+        assert_eq!(untouched.verify_all(), vec![]);
+        assert!(!PiggUpdater::recover(&pigg.0).unwrap());
+        assert!(!journal_path(&pigg.0).exists());
+    }
+}
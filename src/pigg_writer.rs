@@ -0,0 +1,506 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::{AsRef, TryFrom};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::common::{encode_cp1252, to_u32, write_u16, write_u32};
+use crate::error::{Error, Result};
+use crate::pigg::{
+    Pigg, Storage, ARCHIVE_HEADER_LEN, ARCHIVE_MAGIC, FILE_HEADER_LEN, FILE_HEADER_MAGIC,
+    HEADER_DATA_POOL_MAGIC, NO_HEADER_DATA, READ_VERSION, STRING_POOL_MAGIC,
+};
+
+/// Version of the pigg format we write
+const CREATOR_VERSION: u16 = 2;
+
+/// PIGG creator
+///
+/// Files are compressed as they're added and held in memory until the pigg is
+/// written out. Files copied out of another pigg without recompressing them
+/// are instead read from that pigg as they're written, so it must outlive
+/// the writer. Files are written sorted by path, with no gaps between them,
+/// so merging an existing pigg into an empty writer compacts it.
+#[derive(Debug, Default)]
+pub struct PiggWriter<'a> {
+    pub(crate) files: BTreeMap<String, PendingFile<'a>>,
+    compression: Compression,
+}
+
+#[derive(Debug)]
+pub(crate) struct PendingFile<'a> {
+    pub size: u32,
+    pub timestamp: u32,
+    pub checksum: u128,
+    /// zlib compressed data, unless `stored` is set
+    pub data: PendingData<'a>,
+    pub stored: bool,
+    pub header_data: Option<Vec<u8>>,
+}
+
+/// Where a pending file's data comes from
+pub(crate) enum PendingData<'a> {
+    Owned(Vec<u8>),
+    /// Copied as-is from another pigg when written
+    Copied {
+        source: &'a dyn RawSource,
+        path: String,
+        len: usize,
+    },
+}
+
+// Written by hand to avoid dumping file data, and as sources aren't Debug
+impl fmt::Debug for PendingData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PendingData::Owned(data) => f.debug_tuple("Owned").field(&data.len()).finish(),
+            PendingData::Copied { path, len, .. } => f
+                .debug_struct("Copied")
+                .field("path", path)
+                .field("len", len)
+                .finish(),
+        }
+    }
+}
+
+/// Anything a `PiggWriter` can copy stored data out of
+pub(crate) trait RawSource {
+    fn raw_data(&self, path: &str) -> Result<Cow<'_, [u8]>>;
+}
+
+impl<S: Storage> RawSource for Pigg<S> {
+    fn raw_data(&self, path: &str) -> Result<Cow<'_, [u8]>> {
+        self.get_raw_data(path)
+    }
+}
+
+impl PendingFile<'_> {
+    /// Length of the data as written
+    pub fn len(&self) -> usize {
+        match &self.data {
+            PendingData::Owned(data) => data.len(),
+            PendingData::Copied { len, .. } => *len,
+        }
+    }
+
+    /// The data as written, reading it from the source pigg if needed
+    pub fn data(&self) -> Result<Cow<'_, [u8]>> {
+        match &self.data {
+            PendingData::Owned(data) => Ok(Cow::Borrowed(data)),
+            PendingData::Copied { source, path, len } => {
+                let data = source.raw_data(path)?;
+                if data.len() != *len {
+                    return Err(Error::ParseError(format!(
+                        "{} changed size while being copied",
+                        path
+                    )));
+                }
+                Ok(data)
+            }
+        }
+    }
+
+    /// `pack_size` as written to the file header, zero for stored data
+    pub fn pack_size(&self) -> u32 {
+        if self.stored {
+            0
+        } else {
+            self.len() as u32
+        }
+    }
+}
+
+impl<'a> PiggWriter<'a> {
+    /// Creates a writer for an empty pigg
+    pub fn new() -> PiggWriter<'a> {
+        PiggWriter::default()
+    }
+
+    /// Sets how hard files are compressed from now on, from 0 (not at all) to
+    /// 9 (smallest), defaulting to 6
+    pub fn set_compression_level(&mut self, level: u32) {
+        self.compression = Compression::new(level.min(9));
+    }
+
+    /// Adds a file to the pigg, replacing any file previously added at the
+    /// same path
+    ///
+    /// A replaced file's header data is kept, see `clear_header_data`. The
+    /// data is stored uncompressed if compressing it doesn't make it any
+    /// smaller.
+    pub fn add(&mut self, path: &str, data: &[u8], timestamp: u32) -> Result<()> {
+        let mut file = self.compress(path, data, timestamp)?;
+        if let Some(replaced) = self.files.remove(path) {
+            file.header_data = replaced.header_data;
+        }
+        self.files.insert(path.to_string(), file);
+        Ok(())
+    }
+
+    /// Copies a file out of an existing pigg, keeping its timestamp and header
+    /// data, and replacing any file previously added at the same path
+    ///
+    /// The data is copied as-is unless `recompress` is set, in which case it's
+    /// decompressed and compressed again at this writer's level.
+    pub fn copy_from<S: Storage>(
+        &mut self,
+        pigg: &'a Pigg<S>,
+        path: &str,
+        recompress: bool,
+    ) -> Result<()> {
+        let raw = pigg.get_raw_file(path)?;
+        let header_data = raw.header_data.map(<[u8]>::to_vec);
+
+        let file = if recompress {
+            let mut file = self.compress(path, &pigg.get_data_cow(path)?, raw.timestamp)?;
+            file.header_data = header_data;
+            file
+        } else {
+            PendingFile {
+                size: raw.size,
+                timestamp: raw.timestamp,
+                checksum: raw.checksum,
+                data: PendingData::Copied {
+                    source: pigg,
+                    path: path.to_string(),
+                    len: raw.len,
+                },
+                stored: raw.stored,
+                header_data,
+            }
+        };
+        self.files.insert(path.to_string(), file);
+        Ok(())
+    }
+
+    /// Copies every file out of an existing pigg, except where this writer
+    /// already holds a newer copy of the path
+    ///
+    /// Merging several piggs keeps the newest copy of each path, with ties
+    /// going to the pigg merged last. See `copy_from` for `recompress`.
+    pub fn merge<S: Storage>(&mut self, pigg: &'a Pigg<S>, recompress: bool) -> Result<()> {
+        for (path, metadata) in pigg.files() {
+            let newer = self
+                .files
+                .get(path)
+                .map_or(true, |current| metadata.timestamp >= current.timestamp);
+            if newer {
+                self.copy_from(pigg, path, recompress)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a previously added file, returning whether it was present
+    pub fn remove(&mut self, path: &str) -> bool {
+        self.files.remove(path).is_some()
+    }
+
+    fn compress(&self, path: &str, data: &[u8], timestamp: u32) -> Result<PendingFile<'a>> {
+        if encode_cp1252(path).is_none() {
+            return Err(Error::EncodeError(format!(
+                "{} can't be represented in Windows-1252",
+                path
+            )));
+        }
+        let size = u32::try_from(data.len())
+            .map_err(|_| Error::EncodeError(format!("{} is too large for a pigg", path)))?;
+
+        let checksum = u128::from_le_bytes(md5::compute(data).0);
+
+        let mut encoder = ZlibEncoder::new(Vec::with_capacity(data.len()), self.compression);
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+
+        let (data, stored) = if compressed.len() < data.len() {
+            (compressed, false)
+        } else {
+            (data.to_vec(), true)
+        };
+
+        Ok(PendingFile {
+            size,
+            timestamp,
+            checksum,
+            data: PendingData::Owned(data),
+            stored,
+            header_data: None,
+        })
+    }
+
+    /// Attaches header data to a previously added file
+    ///
+    /// See `Pigg::get_header_data`
+    pub fn set_header_data(&mut self, path: &str, header_data: &[u8]) -> Result<()> {
+        let file = self
+            .files
+            .get_mut(path)
+            .ok_or_else(|| Error::ItemNotFound(path.into()))?;
+        file.header_data = Some(header_data.to_vec());
+        Ok(())
+    }
+
+    /// Removes any header data from a previously added file
+    pub fn clear_header_data(&mut self, path: &str) -> Result<()> {
+        let file = self
+            .files
+            .get_mut(path)
+            .ok_or_else(|| Error::ItemNotFound(path.into()))?;
+        file.header_data = None;
+        Ok(())
+    }
+
+    /// Number of files added so far
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Whether no files have been added yet
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Writes the pigg out to the given file, replacing it if it exists
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Writes the pigg out to the given writer
+    pub fn write<W: Write>(&self, mut out: W) -> Result<()> {
+        let mut entries: Vec<_> = self
+            .files
+            .iter()
+            .map(|(path, file)| HeaderEntry {
+                path,
+                size: file.size,
+                timestamp: file.timestamp,
+                offset: 0,
+                checksum: file.checksum,
+                pack_size: file.pack_size(),
+                header_data: file.header_data.as_deref(),
+            })
+            .collect();
+
+        let mut offset = header_block_len(&entries);
+        for (entry, file) in entries.iter_mut().zip(self.files.values()) {
+            entry.offset = offset;
+            offset += file.len();
+        }
+        to_u32(offset, "pigg")?;
+
+        write_header_block(&mut out, &entries)?;
+        for file in self.files.values() {
+            out.write_all(&file.data()?)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Everything recorded about a file in the file headers and pools
+pub(crate) struct HeaderEntry<'a> {
+    pub path: &'a str,
+    pub size: u32,
+    pub timestamp: u32,
+    pub offset: usize,
+    pub checksum: u128,
+    pub pack_size: u32,
+    pub header_data: Option<&'a [u8]>,
+}
+
+/// Size of everything before the file data: the archive header, file headers,
+/// and both pools
+pub(crate) fn header_block_len(entries: &[HeaderEntry]) -> usize {
+    ARCHIVE_HEADER_LEN
+        + FILE_HEADER_LEN * entries.len()
+        + 12
+        + name_pool_size(entries)
+        + 12
+        + header_data_pool_size(entries)
+}
+
+fn name_pool_size(entries: &[HeaderEntry]) -> usize {
+    entries
+        .iter()
+        .map(|entry| 4 + encoded_path(entry.path).len() + 1)
+        .sum()
+}
+
+fn header_data_pool_size(entries: &[HeaderEntry]) -> usize {
+    entries
+        .iter()
+        .filter_map(|entry| entry.header_data)
+        .map(|data| 4 + data.len())
+        .sum()
+}
+
+/// Writes everything before the file data, with the files in the given order
+pub(crate) fn write_header_block<W: Write>(out: &mut W, entries: &[HeaderEntry]) -> Result<()> {
+    write_u32(out, ARCHIVE_MAGIC)?;
+    write_u16(out, CREATOR_VERSION)?;
+    write_u16(out, READ_VERSION)?;
+    write_u16(out, ARCHIVE_HEADER_LEN as u16)?;
+    write_u16(out, FILE_HEADER_LEN as u16)?;
+    write_u32(out, to_u32(entries.len(), "file count")?)?;
+
+    let mut header_data_id = 0;
+    for (name_id, entry) in entries.iter().enumerate() {
+        write_u32(out, FILE_HEADER_MAGIC)?;
+        out.write_all(&(name_id as i32).to_le_bytes())?;
+        write_u32(out, entry.size)?;
+        write_u32(out, entry.timestamp)?;
+        write_u32(out, to_u32(entry.offset, "pigg")?)?;
+        write_u32(out, 0)?;
+        if entry.header_data.is_some() {
+            write_u32(out, header_data_id)?;
+            header_data_id += 1;
+        } else {
+            write_u32(out, NO_HEADER_DATA)?;
+        }
+        out.write_all(&entry.checksum.to_le_bytes())?;
+        write_u32(out, entry.pack_size)?;
+    }
+
+    write_u32(out, STRING_POOL_MAGIC)?;
+    write_u32(out, entries.len() as u32)?;
+    write_u32(out, to_u32(name_pool_size(entries), "name pool")?)?;
+    for entry in entries {
+        let path = encoded_path(entry.path);
+        write_u32(out, path.len() as u32 + 1)?;
+        out.write_all(&path)?;
+        out.write_all(&[0])?;
+    }
+
+    write_u32(out, HEADER_DATA_POOL_MAGIC)?;
+    write_u32(out, header_data_id)?;
+    write_u32(
+        out,
+        to_u32(header_data_pool_size(entries), "header data pool")?,
+    )?;
+    for data in entries.iter().filter_map(|entry| entry.header_data) {
+        write_u32(out, to_u32(data.len(), "header data")?)?;
+        out.write_all(data)?;
+    }
+
+    Ok(())
+}
+
+/// Encodes a path for the name pool
+///
+/// Paths are checked as files are added, and paths copied out of a pigg were
+/// decoded from Windows-1252 in the first place, so the fallback to UTF-8 is
+/// never expected to be hit.
+fn encoded_path(path: &str) -> Cow<'_, [u8]> {
+    encode_cp1252(path).unwrap_or(Cow::Borrowed(path.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Corruption;
+
+    fn written(writer: &PiggWriter) -> Vec<u8> {
+        let mut bytes = vec![];
+        writer.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn written_pigg_reads_back() {
+        let compressible = b"texture ".repeat(100);
+        let mut writer = PiggWriter::new();
+        writer.add("bin/powers.bin", &compressible, 100).unwrap();
+        writer.add("texts/tiny.txt", b"x", 200).unwrap();
+        writer.add("empty", b"", 300).unwrap();
+        writer.set_header_data("bin/powers.bin", b"hdr").unwrap();
+
+        let pigg = Pigg::from_vec(written(&writer)).unwrap();
+        assert_eq!(pigg.len(), 3);
+        assert_eq!(pigg.get_data("bin/powers.bin").unwrap(), compressible);
+        assert_eq!(pigg.get_data("texts/tiny.txt").unwrap(), b"x");
+        assert_eq!(pigg.get_data("empty").unwrap(), b"");
+        assert_eq!(
+            pigg.get_header_data("bin/powers.bin").unwrap(),
+            Some(&b"hdr"[..])
+        );
+        assert_eq!(pigg.get_header_data("texts/tiny.txt").unwrap(), None);
+
+        let powers = pigg.metadata("bin/powers.bin").unwrap();
+        assert_eq!(powers.size as usize, compressible.len());
+        assert!(powers.pack_size > 0 && powers.pack_size < powers.size);
+        assert_eq!(powers.timestamp, 100);
+        assert_eq!(
+            powers.checksum,
+            u128::from_le_bytes(md5::compute(&compressible).0)
+        );
+        // Compressing a single byte only makes it larger
+        assert_eq!(pigg.metadata("texts/tiny.txt").unwrap().pack_size, 0);
+
+        assert_eq!(pigg.verify_all(), vec![]);
+    }
+
+    #[test]
+    fn verify_all_catches_damage_to_written_data() {
+        let mut writer = PiggWriter::new();
+        writer.set_compression_level(0);
+        writer.add("a.txt", b"stored as is", 1).unwrap();
+        let mut bytes = written(&writer);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        let pigg = Pigg::from_vec(bytes).unwrap();
+        assert!(matches!(
+            &pigg.verify_all()[..],
+            [Corruption::ChecksumMismatch { path, .. }] if path == "a.txt"
+        ));
+    }
+
+    #[test]
+    fn merge_and_repack_round_trip() {
+        let mut old = PiggWriter::new();
+        old.add("a.txt", &b"old a ".repeat(50), 1).unwrap();
+        old.add("b.txt", b"b", 1).unwrap();
+        let old = Pigg::from_vec(written(&old)).unwrap();
+
+        let mut new = PiggWriter::new();
+        new.add("a.txt", &b"new a ".repeat(50), 2).unwrap();
+        let new = Pigg::from_vec(written(&new)).unwrap();
+
+        let mut merged = PiggWriter::new();
+        merged.merge(&old, false).unwrap();
+        merged.merge(&new, true).unwrap();
+        let merged = Pigg::from_vec(written(&merged)).unwrap();
+
+        assert_eq!(merged.get_data("a.txt").unwrap(), b"new a ".repeat(50));
+        assert_eq!(merged.get_data("b.txt").unwrap(), b"b");
+        assert_eq!(merged.verify_all(), vec![]);
+    }
+
+    #[test]
+    fn copies_are_read_from_the_source_when_written() {
+        let mut old = PiggWriter::new();
+        old.add("a.txt", &b"copied ".repeat(50), 1).unwrap();
+        old.set_header_data("a.txt", b"hdr").unwrap();
+        let old = Pigg::from_vec(written(&old)).unwrap();
+
+        let mut copy = PiggWriter::new();
+        copy.merge(&old, false).unwrap();
+        assert!(matches!(
+            copy.files["a.txt"].data,
+            PendingData::Copied { .. }
+        ));
+
+        let copy = Pigg::from_vec(written(&copy)).unwrap();
+        assert_eq!(copy.get_data("a.txt").unwrap(), b"copied ".repeat(50));
+        assert_eq!(copy.get_header_data("a.txt").unwrap(), Some(&b"hdr"[..]));
+        assert_eq!(copy.verify_all(), vec![]);
+    }
+}